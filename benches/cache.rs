@@ -0,0 +1,74 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use serde::{Deserialize, Serialize};
+use solar::cache::{MemCache, TypedCache};
+use tokio::runtime::Runtime;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Account {
+    owner: String,
+    lamports: u64,
+    data: Vec<u64>,
+}
+
+fn account(len: usize) -> Account {
+    Account {
+        owner: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string(),
+        lamports: 2_039_280,
+        data: (0..len as u64).collect(),
+    }
+}
+
+fn bench_get(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("get");
+
+    for len in [16, 1024, 16 * 1024] {
+        let mem_cache = MemCache::new();
+        let typed_cache = TypedCache::<String, Account>::new();
+        runtime.block_on(async {
+            mem_cache.set(&"account", account(len), None).await;
+            typed_cache
+                .set("account".to_string(), account(len), None)
+                .await;
+        });
+
+        group.bench_with_input(BenchmarkId::new("MemCache", len), &len, |b, _| {
+            b.to_async(&runtime)
+                .iter(|| async { mem_cache.get::<_, Account>(&"account").await.unwrap() })
+        });
+        group.bench_with_input(BenchmarkId::new("TypedCache", len), &len, |b, _| {
+            b.to_async(&runtime)
+                .iter(|| async { typed_cache.get("account").await.unwrap() })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_set(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("set");
+
+    for len in [16, 1024, 16 * 1024] {
+        let value = account(len);
+        let mem_cache = MemCache::new();
+        let typed_cache = TypedCache::<String, Account>::new();
+
+        group.bench_with_input(BenchmarkId::new("MemCache", len), &len, |b, _| {
+            b.to_async(&runtime)
+                .iter(|| async { mem_cache.set(&"account", value.clone(), None).await })
+        });
+        group.bench_with_input(BenchmarkId::new("TypedCache", len), &len, |b, _| {
+            b.to_async(&runtime).iter(|| async {
+                typed_cache
+                    .set("account".to_string(), value.clone(), None)
+                    .await
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_get, bench_set);
+criterion_main!(benches);
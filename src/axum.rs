@@ -58,6 +58,14 @@ impl ErrorResponse {
             code: StatusCode::NOT_FOUND,
         }
     }
+
+    pub fn too_many_requests() -> Self {
+        Self {
+            message: "too many requests".to_string(),
+            status: ErrorStatus::Error,
+            code: StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
 }
 
 impl ErrorResponse {
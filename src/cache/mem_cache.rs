@@ -0,0 +1,1387 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use super::stats::StatsCounter;
+pub use super::storage::{Data, EvictionPolicy, Removal, RemovalCause};
+use super::storage::{Limits, Store};
+use super::{CacheBackend, CacheStats};
+
+/// Value returned by the loader of [`MemCache::cached_expiring`] together with how long it
+/// should be cached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expiring<V> {
+    pub value: V,
+    pub expires_in: Option<Duration>,
+}
+
+impl<V> Expiring<V> {
+    pub fn new(value: V, expires_in: Option<Duration>) -> Self {
+        Self { value, expires_in }
+    }
+}
+
+/// TTLs for [`MemCache::cached_negative`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NegativeCaching {
+    /// TTL of loaded values, the default TTL when `None`.
+    pub expires_in: Option<Duration>,
+    /// How long a loader error is served from the cache. Errors are not cached when `None`.
+    pub error_ttl: Option<Duration>,
+    /// TTL of values serializing to `null`, such as `Ok(None)`, `expires_in` applies when
+    /// `None`.
+    pub empty_ttl: Option<Duration>,
+}
+
+impl NegativeCaching {
+    pub fn new(expires_in: Option<Duration>) -> Self {
+        Self {
+            expires_in,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_error_ttl(mut self, error_ttl: Duration) -> Self {
+        self.error_ttl = Some(error_ttl);
+        self
+    }
+
+    pub fn with_empty_ttl(mut self, empty_ttl: Duration) -> Self {
+        self.empty_ttl = Some(empty_ttl);
+        self
+    }
+}
+
+/// Loader result stored by [`MemCache::cached_negative`], as `{"ok": ...}` or `{"err": ...}`
+/// so that no value can be read back as an error or the other way around.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome<V, E> {
+    Ok(V),
+    Err(E),
+}
+
+const EMPTY_OUTCOME: &str = r#"{"ok":null}"#;
+
+/// Soft and hard TTLs for [`MemCache::cached_stale_while_revalidate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Freshness {
+    /// Past this age the cached value is still served, but refreshed in the background.
+    pub soft_ttl: Duration,
+    /// Past this age the value is gone and the caller waits for a fresh one.
+    pub hard_ttl: Duration,
+    /// Keys read within this window before their `soft_ttl` are refreshed ahead of time.
+    pub refresh_ahead: Option<Duration>,
+}
+
+impl Freshness {
+    pub fn new(soft_ttl: Duration, hard_ttl: Duration) -> Self {
+        Self {
+            soft_ttl,
+            hard_ttl: hard_ttl.max(soft_ttl),
+            refresh_ahead: None,
+        }
+    }
+
+    pub fn with_refresh_ahead(mut self, refresh_ahead: Duration) -> Self {
+        self.refresh_ahead = Some(refresh_ahead);
+        self
+    }
+
+    fn needs_refresh(&self, data: &Data, now: Instant) -> bool {
+        let Some(stale_at) = data.stale_at else {
+            return false;
+        };
+
+        stale_at
+            .checked_sub(self.refresh_ahead.unwrap_or_default())
+            .is_none_or(|refresh_at| refresh_at <= now)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemCache {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    store: Arc<Store<String, String>>,
+    in_flight: Mutex<HashMap<String, watch::Receiver<Option<FlightResult>>>>,
+    namespaces: Mutex<HashMap<String, Arc<StatsCounter>>>,
+}
+
+/// Outcome of a coalesced load: the serialized value or the type-erased error.
+type FlightResult = Result<String, Arc<dyn Any + Send + Sync>>;
+
+enum Flight<'a> {
+    Leader(FlightGuard<'a>),
+    Follower(watch::Receiver<Option<FlightResult>>),
+}
+
+/// Held by the caller that runs the loader. Dropping it unregisters the flight, so
+/// if the leader is cancelled the waiting callers retry and one of them takes over.
+struct FlightGuard<'a> {
+    inner: &'a Inner,
+    key: String,
+    sender: watch::Sender<Option<FlightResult>>,
+}
+
+impl FlightGuard<'_> {
+    fn complete(self, result: FlightResult) {
+        self.sender.send_replace(Some(result));
+    }
+}
+
+impl Drop for FlightGuard<'_> {
+    fn drop(&mut self) {
+        self.inner.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Builder for a [`MemCache`] with bounded capacity.
+///
+/// Example
+/// ```rust
+/// use solar::cache::{EvictionPolicy, MemCache};
+///
+/// let cache = MemCache::builder()
+///     .max_entries(10_000)
+///     .max_bytes(64 * 1024 * 1024)
+///     .eviction_policy(EvictionPolicy::Lfu)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemCacheBuilder {
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+    eviction_policy: EvictionPolicy,
+    cancel_token: Option<CancellationToken>,
+}
+
+impl MemCacheBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of entries kept in the cache.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Maximum total size of the serialized values kept in the cache. A value larger than
+    /// that is not stored, and the value it would have replaced stays in the cache.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    /// Stops the expiry sweeper once the token is cancelled. The sweeper also
+    /// stops on its own when the last handle to the cache is dropped.
+    pub fn cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.cancel_token = Some(cancel_token);
+        self
+    }
+
+    pub fn build(self) -> MemCache {
+        let limits = Limits {
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
+            policy: self.eviction_policy,
+            weigher: String::len,
+        };
+
+        MemCache {
+            inner: Arc::new(Inner {
+                store: Arc::new(Store::new(limits, self.cancel_token)),
+                in_flight: Mutex::new(HashMap::new()),
+                namespaces: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+}
+
+impl Default for MemCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemCache {
+    pub fn new() -> Self {
+        MemCacheBuilder::new().build()
+    }
+
+    pub fn builder() -> MemCacheBuilder {
+        MemCacheBuilder::new()
+    }
+
+    pub async fn len(&self) -> usize {
+        self.inner.store.len().await
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Snapshot of the hit, miss, insertion, eviction, expiration and loader error counters
+    /// together with the current size of the cache.
+    pub async fn stats(&self) -> CacheStats {
+        self.inner.store.stats().await
+    }
+
+    pub async fn get<K, V>(&self, key: &K) -> Option<V>
+    where
+        K: Serialize,
+        V: for<'de> Deserialize<'de>,
+    {
+        let key = serde_json::to_string(key).ok()?;
+        let data = self.get_entry(&key).await?;
+
+        let data: V = serde_json::from_str(&data.value).ok()?;
+        Some(data)
+    }
+
+    pub(super) async fn get_entry(&self, key: &str) -> Option<Data> {
+        self.inner.store.get(key).await
+    }
+
+    pub async fn set<K: Serialize, V: Serialize>(
+        &self,
+        key: &K,
+        value: V,
+        expires_in: Option<Duration>,
+    ) -> Option<V> {
+        let key = serde_json::to_string(key).ok()?;
+        let value_json = serde_json::to_string(&value).ok()?;
+
+        self.insert_entry(key, Data::new(value_json, expires_in))
+            .await;
+
+        Some(value)
+    }
+
+    /// Same as [`MemCache::set`], additionally attaching `tags` to the entry so that it can
+    /// be dropped together with other entries through [`MemCache::invalidate_tag`].
+    pub async fn set_with_tags<K: Serialize, V: Serialize>(
+        &self,
+        key: &K,
+        value: V,
+        expires_in: Option<Duration>,
+        tags: &[&str],
+    ) -> Option<V> {
+        let key = serde_json::to_string(key).ok()?;
+        let value_json = serde_json::to_string(&value).ok()?;
+        let tags = tags.iter().map(|tag| tag.to_string()).collect();
+
+        self.insert_entry(key, Data::new(value_json, expires_in).with_tags(tags))
+            .await;
+
+        Some(value)
+    }
+
+    /// Removes the entry stored under `key`, returns whether there was one.
+    pub async fn remove<K: Serialize>(&self, key: &K) -> bool {
+        let Ok(key) = serde_json::to_string(key) else {
+            return false;
+        };
+
+        self.inner.store.remove(key.as_str()).await
+    }
+
+    /// Removes every entry whose serialized key starts with `prefix` and returns how many
+    /// were removed. Keys are JSON, so a string key `"wallet:abc"` is stored as
+    /// `"\"wallet:abc\""` and a tuple key `("wallet", "abc")` as `["wallet","abc"]`.
+    pub async fn invalidate_prefix(&self, prefix: &str) -> usize {
+        self.inner.store.remove_prefix(prefix).await
+    }
+
+    /// Removes every entry stored with `tag` and returns how many were removed.
+    pub async fn invalidate_tag(&self, tag: &str) -> usize {
+        self.inner.store.remove_tag(tag).await
+    }
+
+    pub async fn clear(&self) {
+        self.inner.store.clear().await;
+    }
+
+    /// Calls `listener` for every entry that leaves the cache from now on, with its
+    /// serialized key and why it was removed. The listener runs on the task that removed the
+    /// entry, so it should hand off anything slow.
+    pub fn on_removal(&self, listener: impl Fn(&Removal) + Send + Sync + 'static) {
+        self.inner.store.add_listener(Box::new(move |removal| {
+            listener(removal);
+            true
+        }));
+    }
+
+    /// Same as [`MemCache::on_removal`], delivering the removals over a channel instead.
+    /// Dropping the receiver unsubscribes.
+    ///
+    /// Example
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use solar::cache::{MemCache, RemovalCause};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let cache = MemCache::new();
+    /// let mut removals = cache.subscribe_removals();
+    ///
+    /// cache.set(&"account", 1, Some(Duration::from_millis(10))).await;
+    ///
+    /// let removal = removals.recv().await.unwrap();
+    /// assert_eq!(removal.cause, RemovalCause::Expired);
+    /// // unsubscribe from the account websocket
+    /// # }
+    /// ```
+    pub fn subscribe_removals(&self) -> mpsc::UnboundedReceiver<Removal> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.inner.store.add_listener(Box::new(move |removal| {
+            sender.send(removal.clone()).is_ok()
+        }));
+
+        receiver
+    }
+
+    pub(super) async fn insert_entry(&self, key: String, data: Data) -> bool {
+        self.inner.store.insert(key, data).await
+    }
+
+    /// Counters of the `name` namespace, shared by all its handles.
+    pub(super) fn namespace_stats(&self, name: &str) -> (Arc<StatsCounter>, bool) {
+        let mut namespaces = self.inner.namespaces.lock().unwrap();
+        match namespaces.get(name) {
+            Some(stats) => (Arc::clone(stats), false),
+            None => {
+                let stats = Arc::new(StatsCounter::default());
+                namespaces.insert(name.to_string(), Arc::clone(&stats));
+                (stats, true)
+            }
+        }
+    }
+
+    pub(super) async fn entries(&self) -> Vec<(String, Data)> {
+        self.inner.store.entries().await
+    }
+
+    pub(super) fn store(&self) -> &Store<String, String> {
+        &self.inner.store
+    }
+
+    pub async fn cached<F, Fut, K, V, E>(
+        &self,
+        action_fn: F,
+        key: K,
+        expires_in: Option<Duration>,
+    ) -> Result<V, E>
+    where
+        K: Serialize + std::fmt::Debug,
+        V: for<'de> Deserialize<'de> + Serialize,
+        Fut: Future<Output = Result<V, E>>,
+        F: FnOnce() -> Fut + Send + 'static,
+    {
+        let data: Option<V> = self.get(&key).await;
+        if let Some(data) = data {
+            #[cfg(feature = "log")]
+            log::debug!("[cache] cache hit key={key:?}");
+
+            return Ok(data);
+        }
+
+        match action_fn().await {
+            Err(err) => {
+                self.inner.store.stats.loader_error();
+                Err(err)
+            }
+            Ok(value) => {
+                let value = self
+                    .set(&key, value, expires_in)
+                    .await
+                    .expect("failed to set cache");
+
+                #[cfg(feature = "log")]
+                log::debug!("[cache] new entry key={key:?}");
+
+                Ok(value)
+            }
+        }
+    }
+
+    /// Same as [`MemCache::cached`], but the loader decides how long each value is kept, for
+    /// example longer for finalized data than for processed data.
+    ///
+    /// Example
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use solar::cache::{Expiring, MemCache};
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let cache = MemCache::new();
+    /// let slot = cache
+    ///     .cached_expiring(
+    ///         || async {
+    ///             let (slot, finalized) = (42u64, true);
+    ///             let ttl = if finalized { 600 } else { 2 };
+    ///             Ok::<_, String>(Expiring::new(slot, Some(Duration::from_secs(ttl))))
+    ///         },
+    ///         "slot",
+    ///     )
+    ///     .await;
+    /// # assert_eq!(slot, Ok(42));
+    /// # }
+    /// ```
+    pub async fn cached_expiring<F, Fut, K, V, E>(&self, action_fn: F, key: K) -> Result<V, E>
+    where
+        K: Serialize + std::fmt::Debug,
+        V: for<'de> Deserialize<'de> + Serialize,
+        Fut: Future<Output = Result<Expiring<V>, E>>,
+        F: FnOnce() -> Fut,
+    {
+        if let Some(value) = self.get(&key).await {
+            #[cfg(feature = "log")]
+            log::debug!("[cache] cache hit key={key:?}");
+
+            return Ok(value);
+        }
+
+        match action_fn().await {
+            Err(err) => {
+                self.inner.store.stats.loader_error();
+                Err(err)
+            }
+            Ok(Expiring { value, expires_in }) => {
+                let value = self
+                    .set(&key, value, expires_in)
+                    .await
+                    .expect("failed to set cache");
+
+                #[cfg(feature = "log")]
+                log::debug!("[cache] new entry key={key:?} expires_in={expires_in:?}");
+
+                Ok(value)
+            }
+        }
+    }
+
+    /// Same as [`MemCache::cached`], but loader errors and empty values can be cached for
+    /// their own, usually shorter, TTL so that e.g. a missing mint is not looked up on every
+    /// request. A cached error is returned as is until it expires.
+    ///
+    /// Entries are stored along with whether they are a value or an error, so they can only
+    /// be read back through this method.
+    pub async fn cached_negative<F, Fut, K, V, E>(
+        &self,
+        action_fn: F,
+        key: K,
+        policy: NegativeCaching,
+    ) -> Result<V, E>
+    where
+        K: Serialize + std::fmt::Debug,
+        V: for<'de> Deserialize<'de> + Serialize,
+        E: for<'de> Deserialize<'de> + Serialize,
+        Fut: Future<Output = Result<V, E>>,
+        F: FnOnce() -> Fut,
+    {
+        let Ok(storage_key) = serde_json::to_string(&key) else {
+            return action_fn().await;
+        };
+
+        let cached = self.get_entry(&storage_key).await;
+        match cached.and_then(|data| serde_json::from_str(&data.value).ok()) {
+            Some(Outcome::Ok(value)) => {
+                #[cfg(feature = "log")]
+                log::debug!("[cache] cache hit key={key:?}");
+
+                return Ok(value);
+            }
+            Some(Outcome::Err(err)) => {
+                #[cfg(feature = "log")]
+                log::debug!("[cache] cached error key={key:?}");
+
+                return Err(err);
+            }
+            None => {}
+        }
+
+        match action_fn().await {
+            Err(err) => {
+                self.inner.store.stats.loader_error();
+
+                let cached = policy.error_ttl.and_then(|error_ttl| {
+                    let err_json = serde_json::to_string(&Outcome::<(), _>::Err(&err)).ok()?;
+                    Some(Data::new(err_json, Some(error_ttl)))
+                });
+                if let Some(data) = cached {
+                    self.insert_entry(storage_key, data).await;
+                }
+
+                Err(err)
+            }
+            Ok(value) => {
+                let Ok(value_json) = serde_json::to_string(&Outcome::<_, ()>::Ok(&value)) else {
+                    return Ok(value);
+                };
+                let expires_in = match policy.empty_ttl {
+                    Some(empty_ttl) if value_json == EMPTY_OUTCOME => Some(empty_ttl),
+                    _ => policy.expires_in,
+                };
+                self.insert_entry(storage_key, Data::new(value_json, expires_in))
+                    .await;
+
+                #[cfg(feature = "log")]
+                log::debug!("[cache] new entry key={key:?}");
+
+                Ok(value)
+            }
+        }
+    }
+
+    /// Same as [`MemCache::cached`], but concurrent callers missing the same key share a
+    /// single `action_fn` run: the first caller executes it and the others await its
+    /// result, including the error, which is why `E` has to be `Clone`.
+    pub async fn cached_coalesced<F, Fut, K, V, E>(
+        &self,
+        action_fn: F,
+        key: K,
+        expires_in: Option<Duration>,
+    ) -> Result<V, E>
+    where
+        K: Serialize + std::fmt::Debug,
+        V: for<'de> Deserialize<'de> + Serialize,
+        Fut: Future<Output = Result<V, E>>,
+        F: FnOnce() -> Fut,
+        E: Clone + Send + Sync + 'static,
+    {
+        let Ok(storage_key) = serde_json::to_string(&key) else {
+            return action_fn().await;
+        };
+
+        self.load_coalesced(action_fn, &key, storage_key, expires_in, None)
+            .await
+    }
+
+    /// Serves cached values according to `freshness`: past the soft TTL the stale value is
+    /// returned right away while `action_fn` refreshes it in the background, past the hard
+    /// TTL callers wait for `action_fn` like in [`MemCache::cached_coalesced`]. With
+    /// [`Freshness::with_refresh_ahead`] keys that keep being read are renewed before they
+    /// ever turn stale. At most one refresh per key runs at a time.
+    ///
+    /// Example
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// use solar::cache::{Freshness, MemCache};
+    ///
+    /// # async fn fetch_price() -> Result<f64, String> { Ok(1.0) }
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let cache = MemCache::new();
+    /// let freshness = Freshness::new(Duration::from_secs(5), Duration::from_secs(60))
+    ///     .with_refresh_ahead(Duration::from_secs(1));
+    ///
+    /// let price = cache
+    ///     .cached_stale_while_revalidate(fetch_price, "sol_usd", freshness)
+    ///     .await;
+    /// # assert_eq!(price, Ok(1.0));
+    /// # }
+    /// ```
+    pub async fn cached_stale_while_revalidate<F, Fut, K, V, E>(
+        &self,
+        action_fn: F,
+        key: K,
+        freshness: Freshness,
+    ) -> Result<V, E>
+    where
+        K: Serialize + std::fmt::Debug,
+        V: for<'de> Deserialize<'de> + Serialize + Send + 'static,
+        Fut: Future<Output = Result<V, E>> + Send + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        E: Clone + Send + Sync + 'static,
+    {
+        let Ok(storage_key) = serde_json::to_string(&key) else {
+            return action_fn().await;
+        };
+
+        let cached = self.get_entry(&storage_key).await.and_then(|data| {
+            let value: V = serde_json::from_str(&data.value).ok()?;
+            Some((value, data))
+        });
+        if let Some((value, data)) = cached {
+            if freshness.needs_refresh(&data, Instant::now()) {
+                #[cfg(feature = "log")]
+                log::debug!("[cache] stale hit key={key:?}");
+
+                self.refresh_in_background(action_fn, storage_key, freshness);
+            } else {
+                #[cfg(feature = "log")]
+                log::debug!("[cache] cache hit key={key:?}");
+            }
+
+            return Ok(value);
+        }
+
+        self.load_coalesced(
+            action_fn,
+            &key,
+            storage_key,
+            Some(freshness.hard_ttl),
+            Some(freshness.soft_ttl),
+        )
+        .await
+    }
+
+    async fn load_coalesced<F, Fut, K, V, E>(
+        &self,
+        action_fn: F,
+        key: &K,
+        storage_key: String,
+        expires_in: Option<Duration>,
+        stale_in: Option<Duration>,
+    ) -> Result<V, E>
+    where
+        K: Serialize + std::fmt::Debug,
+        V: for<'de> Deserialize<'de> + Serialize,
+        Fut: Future<Output = Result<V, E>>,
+        F: FnOnce() -> Fut,
+        E: Clone + Send + Sync + 'static,
+    {
+        let flight = loop {
+            if let Some(data) = self.get(&key).await {
+                #[cfg(feature = "log")]
+                log::debug!("[cache] cache hit key={key:?}");
+
+                return Ok(data);
+            }
+
+            match self.join_flight(&storage_key) {
+                Flight::Leader(flight) => break flight,
+                Flight::Follower(receiver) => {
+                    if let Some(result) = Self::follow(receiver).await {
+                        #[cfg(feature = "log")]
+                        log::debug!("[cache] coalesced key={key:?}");
+
+                        return result;
+                    }
+                }
+            }
+        };
+
+        // the previous leader may have filled the cache between our lookup and joining, the
+        // lookup above already counted the miss
+        let filled = self.inner.store.peek(storage_key.as_str()).await;
+        if let Some(value) = filled.and_then(|data| serde_json::from_str(&data.value).ok()) {
+            return Ok(value);
+        }
+
+        match action_fn().await {
+            Err(err) => {
+                self.inner.store.stats.loader_error();
+                flight.complete(Err(Arc::new(err.clone())));
+                Err(err)
+            }
+            Ok(value) => {
+                // the followers load it again themselves
+                let Ok(value_json) = serde_json::to_string(&value) else {
+                    return Ok(value);
+                };
+                let data = Data::new(value_json.clone(), expires_in).with_stale_in(stale_in);
+                self.insert_entry(storage_key, data).await;
+                flight.complete(Ok(value_json));
+
+                #[cfg(feature = "log")]
+                log::debug!("[cache] new entry key={key:?}");
+
+                Ok(value)
+            }
+        }
+    }
+
+    fn refresh_in_background<F, Fut, V, E>(
+        &self,
+        action_fn: F,
+        storage_key: String,
+        freshness: Freshness,
+    ) where
+        V: Serialize + Send + 'static,
+        Fut: Future<Output = Result<V, E>> + Send + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        E: Send + Sync + 'static,
+    {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            // a refresh or a load for this key is already running
+            let Flight::Leader(flight) = cache.join_flight(&storage_key) else {
+                return;
+            };
+
+            match action_fn().await {
+                Err(err) => {
+                    cache.inner.store.stats.loader_error();
+
+                    #[cfg(feature = "log")]
+                    log::warn!("[cache] background refresh failed key={storage_key}");
+
+                    flight.complete(Err(Arc::new(err)));
+                }
+                Ok(value) => {
+                    let Ok(value_json) = serde_json::to_string(&value) else {
+                        return;
+                    };
+                    let data = Data::new(value_json.clone(), Some(freshness.hard_ttl))
+                        .with_stale_in(Some(freshness.soft_ttl));
+                    cache.insert_entry(storage_key, data).await;
+                    flight.complete(Ok(value_json));
+                }
+            }
+        });
+    }
+
+    fn join_flight(&self, key: &str) -> Flight<'_> {
+        let mut in_flight = self.inner.in_flight.lock().unwrap();
+        if let Some(receiver) = in_flight.get(key) {
+            return Flight::Follower(receiver.clone());
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        in_flight.insert(key.to_string(), receiver);
+
+        Flight::Leader(FlightGuard {
+            inner: &self.inner,
+            key: key.to_string(),
+            sender,
+        })
+    }
+
+    /// Waits for the leader of a flight. Returns `None` if the leader went away without a
+    /// result or produced one of a different type, in which case the caller retries.
+    async fn follow<V, E>(
+        mut receiver: watch::Receiver<Option<FlightResult>>,
+    ) -> Option<Result<V, E>>
+    where
+        V: for<'de> Deserialize<'de>,
+        E: Clone + 'static,
+    {
+        let result = receiver.wait_for(Option::is_some).await.ok()?.clone()?;
+        match result {
+            Ok(value_json) => serde_json::from_str(&value_json).ok().map(Ok),
+            Err(err) => err.downcast_ref::<E>().cloned().map(Err),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for MemCache {
+    async fn get_raw(&self, key: &str) -> Option<String> {
+        self.get_entry(key).await.map(|data| data.value)
+    }
+
+    async fn get_raw_with_ttl(&self, key: &str) -> Option<(String, Option<Duration>)> {
+        let data = self.get_entry(key).await?;
+        let ttl = data
+            .expires_at
+            .map(|expires_at| expires_at.saturating_duration_since(Instant::now()));
+
+        Some((data.value, ttl))
+    }
+
+    async fn set_raw(&self, key: String, value: String, expires_in: Option<Duration>) {
+        self.insert_entry(key, Data::new(value, expires_in)).await;
+    }
+
+    async fn remove_raw(&self, key: &str) -> bool {
+        self.inner.store.remove(key).await
+    }
+
+    fn record_loader_error(&self) {
+        self.inner.store.stats.loader_error();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::task::JoinSet;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lru_evicts_oldest() {
+        let cache = MemCache::builder().max_entries(2).build();
+
+        cache.set(&"a", 1, None).await;
+        cache.set(&"b", 2, None).await;
+        cache.set(&"c", 3, None).await;
+
+        assert_eq!(cache.len().await, 2);
+        assert_eq!(cache.get::<_, i32>(&"a").await, None);
+        assert_eq!(cache.get::<_, i32>(&"b").await, Some(2));
+        assert_eq!(cache.get::<_, i32>(&"c").await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_lru_get_refreshes_recency() {
+        let cache = MemCache::builder().max_entries(2).build();
+
+        cache.set(&"a", 1, None).await;
+        cache.set(&"b", 2, None).await;
+        assert_eq!(cache.get::<_, i32>(&"a").await, Some(1));
+        cache.set(&"c", 3, None).await;
+
+        assert_eq!(cache.get::<_, i32>(&"a").await, Some(1));
+        assert_eq!(cache.get::<_, i32>(&"b").await, None);
+        assert_eq!(cache.get::<_, i32>(&"c").await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_lfu_evicts_least_used() {
+        let cache = MemCache::builder()
+            .max_entries(2)
+            .eviction_policy(EvictionPolicy::Lfu)
+            .build();
+
+        cache.set(&"a", 1, None).await;
+        cache.set(&"b", 2, None).await;
+        for _ in 0..3 {
+            cache.get::<_, i32>(&"a").await;
+        }
+        cache.get::<_, i32>(&"b").await;
+        cache.set(&"c", 3, None).await;
+
+        assert_eq!(cache.get::<_, i32>(&"a").await, Some(1));
+        assert_eq!(cache.get::<_, i32>(&"b").await, None);
+        assert_eq!(cache.get::<_, i32>(&"c").await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_max_bytes() {
+        // every value serializes to 5 bytes
+        let cache = MemCache::builder().max_bytes(10).build();
+
+        cache.set(&"a", "aaa", None).await;
+        cache.set(&"b", "bbb", None).await;
+        cache.set(&"c", "ccc", None).await;
+
+        assert_eq!(cache.len().await, 2);
+        assert_eq!(cache.get::<_, String>(&"a").await, None);
+
+        // larger than the whole cache, never stored
+        cache.set(&"d", "ddddddddddd", None).await;
+        assert_eq!(cache.get::<_, String>(&"d").await, None);
+        assert_eq!(cache.len().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_value_keeps_previous() {
+        let cache = MemCache::builder().max_bytes(10).build();
+        let mut removals = cache.subscribe_removals();
+
+        cache.set(&"a", "aaa", None).await;
+        cache.set(&"a", "aaaaaaaaaaa", None).await;
+
+        assert_eq!(cache.get::<_, String>(&"a").await, Some("aaa".to_string()));
+        assert!(removals.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_entries_are_removed() {
+        let cache = MemCache::new();
+
+        cache.set(&"a", 1, Some(Duration::from_secs(1))).await;
+        cache.set(&"b", 2, Some(Duration::from_secs(5))).await;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(cache.len().await, 1);
+        assert_eq!(cache.get::<_, i32>(&"b").await, Some(2));
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_overwrite_is_not_removed_by_stale_expiry() {
+        let cache = MemCache::new();
+
+        cache.set(&"a", 1, Some(Duration::from_secs(1))).await;
+        cache.set(&"a", 2, Some(Duration::from_secs(10))).await;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(cache.get::<_, i32>(&"a").await, Some(2));
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_earlier_expiry_wakes_sweeper() {
+        let cache = MemCache::new();
+
+        cache.set(&"a", 1, Some(Duration::from_secs(60))).await;
+        tokio::task::yield_now().await;
+        cache.set(&"b", 2, Some(Duration::from_secs(1))).await;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(cache.len().await, 1);
+        assert_eq!(cache.get::<_, i32>(&"a").await, Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cached_coalesced_runs_action_once() {
+        let cache = MemCache::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut tasks = JoinSet::new();
+        for _ in 0..50 {
+            let cache = cache.clone();
+            let calls = calls.clone();
+            tasks.spawn(async move {
+                cache
+                    .cached_coalesced(
+                        || async move {
+                            calls.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            Ok::<_, String>(42)
+                        },
+                        "key",
+                        None,
+                    )
+                    .await
+            });
+        }
+
+        while let Some(result) = tasks.join_next().await {
+            assert_eq!(result.unwrap(), Ok(42));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cold_coalesced_load_is_one_miss() {
+        let cache = MemCache::new();
+
+        let value = cache
+            .cached_coalesced(|| async { Ok::<_, String>(42) }, "key", None)
+            .await;
+        assert_eq!(value, Ok(42));
+
+        let stats = cache.stats().await;
+        assert_eq!((stats.hits, stats.misses), (0, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cached_coalesced_shares_error() {
+        let cache = MemCache::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let load = |cache: MemCache, calls: Arc<AtomicUsize>| async move {
+            cache
+                .cached_coalesced(
+                    || async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        Err::<i32, _>("rpc failed".to_string())
+                    },
+                    "key",
+                    None,
+                )
+                .await
+        };
+
+        let mut tasks = JoinSet::new();
+        for _ in 0..10 {
+            tasks.spawn(load(cache.clone(), calls.clone()));
+        }
+        while let Some(result) = tasks.join_next().await {
+            assert_eq!(result.unwrap(), Err("rpc failed".to_string()));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // errors are not cached, the next call runs the action again
+        assert!(load(cache.clone(), calls.clone()).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cached_coalesced_cancelled_leader() {
+        let cache = MemCache::new();
+
+        let leader = tokio::spawn({
+            let cache = cache.clone();
+            async move {
+                cache
+                    .cached_coalesced(std::future::pending::<Result<i32, String>>, "key", None)
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+
+        let follower = tokio::spawn({
+            let cache = cache.clone();
+            async move {
+                cache
+                    .cached_coalesced(|| async { Ok::<_, String>(7) }, "key", None)
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+
+        leader.abort();
+        assert_eq!(follower.await.unwrap(), Ok(7));
+    }
+
+    async fn load_counted(
+        cache: MemCache,
+        calls: Arc<AtomicUsize>,
+        freshness: Freshness,
+    ) -> Result<usize, String> {
+        cache
+            .cached_stale_while_revalidate(
+                move || async move {
+                    let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    Ok(call)
+                },
+                "key",
+                freshness,
+            )
+            .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_while_revalidate() {
+        let cache = MemCache::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let freshness = Freshness::new(Duration::from_secs(10), Duration::from_secs(60));
+
+        assert_eq!(
+            load_counted(cache.clone(), calls.clone(), freshness).await,
+            Ok(1)
+        );
+
+        // stale values are served without waiting and only one refresh is started
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        let started = Instant::now();
+        for _ in 0..10 {
+            assert_eq!(
+                load_counted(cache.clone(), calls.clone(), freshness).await,
+                Ok(1)
+            );
+        }
+        assert_eq!(started.elapsed(), Duration::ZERO);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(
+            load_counted(cache.clone(), calls.clone(), freshness).await,
+            Ok(2)
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // past the hard ttl callers wait for a fresh value
+        tokio::time::sleep(Duration::from_secs(70)).await;
+        let started = Instant::now();
+        assert_eq!(
+            load_counted(cache.clone(), calls.clone(), freshness).await,
+            Ok(3)
+        );
+        assert_eq!(started.elapsed(), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refresh_ahead() {
+        let cache = MemCache::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let freshness = Freshness::new(Duration::from_secs(10), Duration::from_secs(10))
+            .with_refresh_ahead(Duration::from_secs(2));
+
+        assert_eq!(
+            load_counted(cache.clone(), calls.clone(), freshness).await,
+            Ok(1)
+        );
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(
+            load_counted(cache.clone(), calls.clone(), freshness).await,
+            Ok(1)
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // inside the refresh-ahead window the key is renewed before it expires
+        tokio::time::sleep(Duration::from_secs(4)).await;
+        assert_eq!(
+            load_counted(cache.clone(), calls.clone(), freshness).await,
+            Ok(1)
+        );
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let started = Instant::now();
+        assert_eq!(
+            load_counted(cache.clone(), calls.clone(), freshness).await,
+            Ok(2)
+        );
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_remove_and_clear() {
+        let cache = MemCache::new();
+
+        cache.set(&"a", 1, None).await;
+        cache.set(&"b", 2, None).await;
+
+        assert!(cache.remove(&"a").await);
+        assert!(!cache.remove(&"a").await);
+        assert_eq!(cache.get::<_, i32>(&"a").await, None);
+        assert_eq!(cache.get::<_, i32>(&"b").await, Some(2));
+
+        cache.clear().await;
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_prefix() {
+        let cache = MemCache::new();
+
+        cache.set(&("balance", "wallet1", "sol"), 1, None).await;
+        cache.set(&("balance", "wallet1", "usdc"), 2, None).await;
+        cache.set(&("balance", "wallet2", "sol"), 3, None).await;
+
+        assert_eq!(cache.invalidate_prefix(r#"["balance","wallet1","#).await, 2);
+        assert_eq!(
+            cache.get::<_, i32>(&("balance", "wallet1", "sol")).await,
+            None
+        );
+        assert_eq!(
+            cache.get::<_, i32>(&("balance", "wallet2", "sol")).await,
+            Some(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_tag() {
+        let cache = MemCache::new();
+
+        cache.set_with_tags(&"balance", 1, None, &["wallet1"]).await;
+        cache
+            .set_with_tags(&"positions", 2, None, &["wallet1", "wallet2"])
+            .await;
+        cache.set_with_tags(&"orders", 3, None, &["wallet2"]).await;
+
+        assert_eq!(cache.invalidate_tag("wallet1").await, 2);
+        assert_eq!(cache.get::<_, i32>(&"balance").await, None);
+        assert_eq!(cache.get::<_, i32>(&"positions").await, None);
+        assert_eq!(cache.get::<_, i32>(&"orders").await, Some(3));
+
+        // overwriting an entry replaces its tags
+        cache.set_with_tags(&"orders", 4, None, &["wallet3"]).await;
+        assert_eq!(cache.invalidate_tag("wallet2").await, 0);
+        assert_eq!(cache.invalidate_tag("wallet3").await, 1);
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cached_expiring() {
+        let cache = MemCache::new();
+
+        for (key, ttl) in [("finalized", 60), ("processed", 1)] {
+            let value = cache
+                .cached_expiring(
+                    || async move {
+                        Ok::<_, String>(Expiring::new(ttl, Some(Duration::from_secs(ttl))))
+                    },
+                    key,
+                )
+                .await;
+            assert_eq!(value, Ok(ttl));
+        }
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(cache.get::<_, u64>(&"finalized").await, Some(60));
+        assert_eq!(cache.get::<_, u64>(&"processed").await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cached_negative() {
+        let cache = MemCache::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let policy = NegativeCaching::new(Some(Duration::from_secs(60)))
+            .with_error_ttl(Duration::from_secs(1))
+            .with_empty_ttl(Duration::from_secs(5));
+
+        let load = |result: Result<Option<i32>, String>| {
+            let calls = calls.clone();
+            move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                result
+            }
+        };
+
+        for _ in 0..2 {
+            let value = cache
+                .cached_negative(load(Err("mint not found".to_string())), "mint", policy)
+                .await;
+            assert_eq!(value, Err("mint not found".to_string()));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // the error expired, the next load succeeds with an empty value
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let value = cache.cached_negative(load(Ok(None)), "mint", policy).await;
+        assert_eq!(value, Ok(None));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let (_, ttl) = cache.get_raw_with_ttl("\"mint\"").await.unwrap();
+        assert_eq!(ttl, Some(Duration::from_secs(5)));
+
+        // values shaped like a stored error are still values
+        let tagged = HashMap::from([("err".to_string(), "x".to_string())]);
+        for _ in 0..2 {
+            let value = cache
+                .cached_negative(
+                    || async { Ok::<_, String>(tagged.clone()) },
+                    "tagged",
+                    policy,
+                )
+                .await;
+            assert_eq!(value, Ok(tagged.clone()));
+        }
+
+        // without an error TTL nothing is cached on failure
+        let value = cache
+            .cached_negative(
+                load(Err("rpc failed".to_string())),
+                "other",
+                NegativeCaching::new(None),
+            )
+            .await;
+        assert!(value.is_err());
+        assert_eq!(cache.get_raw("\"other\"").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_unserializable_key_is_not_cached() {
+        let cache = MemCache::new();
+        // JSON object keys must be strings
+        let key = HashMap::from([((1, 2), 3)]);
+
+        let value = cache
+            .cached_coalesced(|| async { Ok::<_, String>(1) }, &key, None)
+            .await;
+        assert_eq!(value, Ok(1));
+        let value = cache
+            .cached_negative(
+                || async { Err::<i32, _>("failed".to_string()) },
+                &key,
+                NegativeCaching::new(None).with_error_ttl(Duration::from_secs(1)),
+            )
+            .await;
+        assert_eq!(value, Err("failed".to_string()));
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_removal_listeners() {
+        let cache = MemCache::builder().max_entries(2).build();
+        let mut removals = cache.subscribe_removals();
+        let removed = Arc::new(AtomicUsize::new(0));
+        cache.on_removal({
+            let removed = removed.clone();
+            move |_| {
+                removed.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        cache.set(&"a", 1, Some(Duration::from_secs(1))).await;
+        cache.set(&"a", 2, Some(Duration::from_secs(1))).await;
+        cache.set(&"b", 3, None).await;
+        cache.set(&"c", 4, None).await;
+        cache.remove(&"b").await;
+        cache.set(&"d", 5, Some(Duration::from_secs(1))).await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let removal = |key: &str, cause| Removal {
+            key: format!("\"{key}\""),
+            cause,
+        };
+        let mut received = Vec::new();
+        while let Ok(removal) = removals.try_recv() {
+            received.push(removal);
+        }
+        assert_eq!(
+            received,
+            vec![
+                removal("a", RemovalCause::Replaced),
+                removal("a", RemovalCause::Evicted),
+                removal("b", RemovalCause::Removed),
+                removal("d", RemovalCause::Expired),
+            ]
+        );
+        assert_eq!(removed.load(Ordering::SeqCst), 4);
+
+        // a dropped receiver unsubscribes
+        drop(removals);
+        cache.clear().await;
+        assert_eq!(removed.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stats() {
+        let cache = MemCache::builder().max_entries(2).build();
+
+        cache.set(&"a", 1, Some(Duration::from_secs(1))).await;
+        cache.set(&"b", 2, None).await;
+        cache.set(&"c", 3, None).await;
+        cache.get::<_, i32>(&"b").await;
+        cache.get::<_, i32>(&"missing").await;
+        let _ = cache
+            .cached(|| async { Err::<i32, _>("rpc failed") }, "d", None)
+            .await;
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.insertions, 3);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.loader_errors, 1);
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.bytes, 2);
+        assert_eq!(stats.hit_ratio(), 1.0 / 3.0);
+
+        cache.set(&"e", 5, Some(Duration::from_secs(1))).await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.expirations, 1);
+        assert_eq!(stats.entries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_keeps_size() {
+        let cache = MemCache::builder().max_entries(2).build();
+
+        cache.set(&"a", 1, None).await;
+        cache.set(&"a", 2, None).await;
+        cache.set(&"b", 3, None).await;
+
+        assert_eq!(cache.len().await, 2);
+        assert_eq!(cache.get::<_, i32>(&"a").await, Some(2));
+        assert_eq!(cache.get::<_, i32>(&"b").await, Some(3));
+    }
+}
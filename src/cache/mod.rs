@@ -0,0 +1,151 @@
+use std::future::Future;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+pub mod mem_cache;
+pub mod namespace;
+#[cfg(feature = "trx_factory")]
+pub mod pg_cache;
+#[cfg(feature = "trx_factory")]
+pub mod pg_invalidation;
+#[cfg(feature = "redis")]
+pub mod redis_cache;
+mod snapshot;
+pub mod stats;
+mod storage;
+pub mod tiered_cache;
+pub mod typed_cache;
+
+pub use mem_cache::{
+    Data, EvictionPolicy, Expiring, Freshness, MemCache, MemCacheBuilder, NegativeCaching, Removal,
+    RemovalCause,
+};
+pub use namespace::Namespace;
+#[cfg(feature = "trx_factory")]
+pub use pg_cache::PgCache;
+#[cfg(feature = "trx_factory")]
+pub use pg_invalidation::{Invalidation, PgInvalidationBus};
+#[cfg(feature = "redis")]
+pub use redis_cache::RedisCache;
+pub use stats::CacheStats;
+pub use tiered_cache::TieredCache;
+pub use typed_cache::{TypedCache, TypedCacheBuilder};
+
+const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Storage behind the `get` / `set` / `cached` surface of a cache. Keys and values are
+/// stored as JSON, so every backend sees the same serialized keys.
+///
+/// Implementors only provide the raw accessors, callers generic over `CacheBackend` can
+/// switch between [`MemCache`] and [`RedisCache`] without touching call sites.
+///
+/// Example
+/// ```rust
+/// use solar::cache::{CacheBackend, MemCache};
+///
+/// async fn token_decimals(cache: &impl CacheBackend, mint: &str) -> Result<u8, String> {
+///     cache
+///         .cached(|| async { Ok(6) }, mint.to_string(), None)
+///         .await
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let cache = MemCache::new();
+///     assert_eq!(token_decimals(&cache, "mint").await, Ok(6));
+/// }
+/// ```
+#[async_trait::async_trait]
+pub trait CacheBackend: Send + Sync {
+    /// Returns the JSON value stored under an already serialized key.
+    async fn get_raw(&self, key: &str) -> Option<String>;
+
+    /// Same as [`CacheBackend::get_raw`], also returning how long the value has left to live
+    /// when the backend knows it.
+    async fn get_raw_with_ttl(&self, key: &str) -> Option<(String, Option<Duration>)> {
+        self.get_raw(key).await.map(|value| (value, None))
+    }
+
+    /// Stores a JSON value under an already serialized key, `None` means the default TTL.
+    async fn set_raw(&self, key: String, value: String, expires_in: Option<Duration>);
+
+    /// Removes the value stored under an already serialized key, returns whether there was one.
+    async fn remove_raw(&self, key: &str) -> bool;
+
+    /// Called by [`CacheBackend::cached`] when the loader fails, for backends keeping stats.
+    fn record_loader_error(&self) {}
+
+    async fn get<K, V>(&self, key: &K) -> Option<V>
+    where
+        K: Serialize + Sync,
+        V: for<'de> Deserialize<'de>,
+    {
+        let key = serde_json::to_string(key).ok()?;
+        let value = self.get_raw(&key).await?;
+
+        serde_json::from_str(&value).ok()
+    }
+
+    async fn set<K, V>(&self, key: &K, value: V, expires_in: Option<Duration>) -> Option<V>
+    where
+        K: Serialize + Sync,
+        V: Serialize + Send,
+    {
+        let key = serde_json::to_string(key).ok()?;
+        let value_json = serde_json::to_string(&value).ok()?;
+
+        self.set_raw(key, value_json, expires_in).await;
+
+        Some(value)
+    }
+
+    async fn remove<K>(&self, key: &K) -> bool
+    where
+        K: Serialize + Sync,
+    {
+        let Ok(key) = serde_json::to_string(key) else {
+            return false;
+        };
+
+        self.remove_raw(&key).await
+    }
+
+    async fn cached<F, Fut, K, V, E>(
+        &self,
+        action_fn: F,
+        key: K,
+        expires_in: Option<Duration>,
+    ) -> Result<V, E>
+    where
+        K: Serialize + std::fmt::Debug + Send + Sync,
+        V: for<'de> Deserialize<'de> + Serialize + Send,
+        Fut: Future<Output = Result<V, E>> + Send,
+        F: FnOnce() -> Fut + Send,
+    {
+        let data: Option<V> = self.get(&key).await;
+        if let Some(data) = data {
+            #[cfg(feature = "log")]
+            log::debug!("[cache] cache hit key={key:?}");
+
+            return Ok(data);
+        }
+
+        let value = match action_fn().await {
+            Ok(value) => value,
+            Err(err) => {
+                self.record_loader_error();
+                return Err(err);
+            }
+        };
+        let value = self
+            .set(&key, value, expires_in)
+            .await
+            .expect("failed to set cache");
+
+        #[cfg(feature = "log")]
+        log::debug!("[cache] new entry key={key:?}");
+
+        Ok(value)
+    }
+}
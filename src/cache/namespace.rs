@@ -0,0 +1,289 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::stats::StatsCounter;
+use super::storage::{Data, RemovalCause};
+use super::{CacheBackend, CacheStats, MemCache};
+
+/// Handle on a [`MemCache`] that keeps its keys apart from other namespaces, created with
+/// [`MemCache::namespace`].
+///
+/// Keys are stored as `<serialized name>:<serialized key>`, so the same `u64` used by two
+/// subsystems no longer collides. The name is serialized as a JSON string, which cannot be
+/// the start of another one, so `"a"` and `"a:b"` are separate namespaces too.
+///
+/// All namespaces share the storage and the capacity limits of the cache they come from,
+/// but each has its own default TTL, stats and invalidation. Handles on the same name share
+/// their stats, so a namespace can be created wherever it is needed.
+///
+/// Example
+/// ```rust
+/// use std::time::Duration;
+///
+/// use solar::cache::{CacheBackend, MemCache};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let cache = MemCache::new();
+/// let prices = cache.namespace("prices").with_default_ttl(Duration::from_secs(5));
+/// let decimals = cache.namespace("decimals");
+///
+/// prices.set(&1u64, 150.0, None).await;
+/// decimals.set(&1u64, 9, None).await;
+///
+/// assert_eq!(prices.get::<_, f64>(&1u64).await, Some(150.0));
+/// assert_eq!(decimals.get::<_, u8>(&1u64).await, Some(9));
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Namespace {
+    cache: MemCache,
+    name: String,
+    prefix: String,
+    default_ttl: Option<Duration>,
+    stats: Arc<StatsCounter>,
+}
+
+impl MemCache {
+    /// Returns a handle storing its entries in this cache under the `name` namespace.
+    pub fn namespace(&self, name: &str) -> Namespace {
+        let prefix = format!("{}:", serde_json::Value::from(name));
+
+        // a single listener per name, however many handles are created on it
+        let (stats, created) = self.namespace_stats(name);
+        if created {
+            let listener_prefix = prefix.clone();
+            let listener_stats = Arc::clone(&stats);
+            self.store().add_listener(Box::new(move |removal| {
+                if removal.key.starts_with(&listener_prefix) {
+                    match removal.cause {
+                        RemovalCause::Evicted => listener_stats.evictions(1),
+                        RemovalCause::Expired => listener_stats.expirations(1),
+                        RemovalCause::Replaced | RemovalCause::Removed => {}
+                    }
+                }
+                true
+            }));
+        }
+
+        Namespace {
+            cache: self.clone(),
+            name: name.to_string(),
+            prefix,
+            default_ttl: None,
+            stats,
+        }
+    }
+}
+
+impl Namespace {
+    /// TTL of entries stored without an explicit `expires_in`, instead of the cache default.
+    pub fn with_default_ttl(mut self, default_ttl: Duration) -> Self {
+        self.default_ttl = Some(default_ttl);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn storage_key(&self, key: &str) -> String {
+        format!("{}{key}", self.prefix)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.cache.store().prefix_usage(&self.prefix).await.0
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Counters of this namespace only, see [`MemCache::stats`] for the whole cache.
+    pub async fn stats(&self) -> CacheStats {
+        let (entries, bytes) = self.cache.store().prefix_usage(&self.prefix).await;
+        self.stats.snapshot(entries, bytes)
+    }
+
+    /// Same as [`MemCache::set_with_tags`], the tags only apply within this namespace.
+    pub async fn set_with_tags<K: Serialize, V: Serialize>(
+        &self,
+        key: &K,
+        value: V,
+        expires_in: Option<Duration>,
+        tags: &[&str],
+    ) -> Option<V> {
+        let key = serde_json::to_string(key).ok()?;
+        let value_json = serde_json::to_string(&value).ok()?;
+        let tags = tags.iter().map(|tag| self.storage_key(tag)).collect();
+
+        let data = Data::new(value_json, expires_in.or(self.default_ttl)).with_tags(tags);
+        if self.cache.insert_entry(self.storage_key(&key), data).await {
+            self.stats.insertion();
+        }
+
+        Some(value)
+    }
+
+    /// Removes every entry of this namespace stored with `tag`.
+    pub async fn invalidate_tag(&self, tag: &str) -> usize {
+        self.cache.invalidate_tag(&self.storage_key(tag)).await
+    }
+
+    /// Removes every entry of this namespace whose serialized key starts with `prefix`.
+    pub async fn invalidate_prefix(&self, prefix: &str) -> usize {
+        self.cache
+            .invalidate_prefix(&self.storage_key(prefix))
+            .await
+    }
+
+    /// Removes every entry of this namespace, leaving the rest of the cache untouched.
+    pub async fn clear(&self) -> usize {
+        self.cache.invalidate_prefix(&self.prefix).await
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for Namespace {
+    async fn get_raw(&self, key: &str) -> Option<String> {
+        self.get_raw_with_ttl(key).await.map(|(value, _)| value)
+    }
+
+    async fn get_raw_with_ttl(&self, key: &str) -> Option<(String, Option<Duration>)> {
+        let result = self.cache.get_raw_with_ttl(&self.storage_key(key)).await;
+        match result {
+            Some(_) => self.stats.hit(),
+            None => self.stats.miss(),
+        }
+
+        result
+    }
+
+    async fn set_raw(&self, key: String, value: String, expires_in: Option<Duration>) {
+        let data = Data::new(value, expires_in.or(self.default_ttl));
+        if self.cache.insert_entry(self.storage_key(&key), data).await {
+            self.stats.insertion();
+        }
+    }
+
+    async fn remove_raw(&self, key: &str) -> bool {
+        self.cache.remove_raw(&self.storage_key(key)).await
+    }
+
+    fn record_loader_error(&self) {
+        self.stats.loader_error();
+        self.cache.record_loader_error();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_keys_do_not_collide() {
+        let cache = MemCache::new();
+        let prices = cache.namespace("prices");
+        let decimals = cache.namespace("decimals");
+
+        prices.set(&1u64, 150.5, None).await;
+        decimals.set(&1u64, 9, None).await;
+        cache.set(&1u64, "plain", None).await;
+
+        assert_eq!(prices.get::<_, f64>(&1u64).await, Some(150.5));
+        assert_eq!(decimals.get::<_, u8>(&1u64).await, Some(9));
+        assert_eq!(
+            cache.get::<_, String>(&1u64).await,
+            Some("plain".to_string())
+        );
+        assert_eq!(cache.len().await, 3);
+
+        assert_eq!(prices.clear().await, 1);
+        assert_eq!(prices.get::<_, f64>(&1u64).await, None);
+        assert_eq!(decimals.get::<_, u8>(&1u64).await, Some(9));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_default_ttl() {
+        let cache = MemCache::new();
+        let prices = cache
+            .namespace("prices")
+            .with_default_ttl(Duration::from_secs(5));
+
+        prices.set(&"sol", 150, None).await;
+        prices
+            .set(&"btc", 60_000, Some(Duration::from_secs(60)))
+            .await;
+        tokio::time::sleep(Duration::from_secs(6)).await;
+
+        assert_eq!(prices.get::<_, i32>(&"sol").await, None);
+        assert_eq!(prices.get::<_, i32>(&"btc").await, Some(60_000));
+
+        let stats = prices.stats().await;
+        assert_eq!(stats.expirations, 1);
+        assert_eq!(stats.entries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_stats_and_invalidation() {
+        let cache = MemCache::builder().max_entries(2).build();
+        let prices = cache.namespace("prices");
+        let wallets = cache.namespace("wallets");
+
+        prices.set_with_tags(&"sol", 150, None, &["spot"]).await;
+        wallets.set_with_tags(&"a", 1, None, &["spot"]).await;
+        let _ = prices
+            .cached(|| async { Err::<i32, _>("rpc failed") }, "btc", None)
+            .await;
+        prices.get::<_, i32>(&"sol").await;
+        // evicts the least recently used entry, the wallet
+        prices.set(&"eth", 3_000, None).await;
+
+        assert_eq!(prices.stats().await.hits, 1);
+        assert_eq!(prices.stats().await.misses, 1);
+        assert_eq!(prices.stats().await.loader_errors, 1);
+        assert_eq!(prices.stats().await.entries, 2);
+        assert_eq!(wallets.stats().await.evictions, 1);
+        assert_eq!(cache.stats().await.loader_errors, 1);
+
+        assert_eq!(prices.invalidate_tag("spot").await, 1);
+        assert_eq!(prices.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_nested_names_are_separate() {
+        let cache = MemCache::new();
+        let parent = cache.namespace("a");
+        let child = cache.namespace("a:b");
+
+        parent.set(&1u64, 1, None).await;
+        child.set(&1u64, 2, None).await;
+        child.set(&2u64, 3, None).await;
+
+        assert_eq!(parent.len().await, 1);
+        assert_eq!(child.len().await, 2);
+        assert_eq!(parent.name(), "a");
+
+        assert_eq!(parent.clear().await, 1);
+        assert_eq!(child.get::<_, i32>(&1u64).await, Some(2));
+        assert_eq!(child.len().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_handles_share_stats() {
+        let cache = MemCache::builder().max_bytes(10).build();
+        for _ in 0..10 {
+            cache.namespace("prices").set(&"sol", 150, None).await;
+        }
+        // larger than the whole cache, not stored
+        cache
+            .namespace("prices")
+            .set(&"btc", "60000000000", None)
+            .await;
+
+        assert_eq!(cache.namespace("prices").stats().await.insertions, 10);
+        assert_eq!(cache.store().listener_count(), 1);
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use eyre::Context;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use super::{CacheBackend, DEFAULT_TTL};
+
+const DEFAULT_TABLE: &str = "solar_cache";
+
+/// [`CacheBackend`] persisted in a Postgres table of `(key, value, expires_at)` rows.
+///
+/// Meant as the L2 of a [`TieredCache`](super::TieredCache) so that restarted processes
+/// do not start with a cold cache. Failing queries are treated as cache misses.
+#[derive(Debug, Clone)]
+pub struct PgCache {
+    pool: PgPool,
+    table: String,
+}
+
+impl PgCache {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            table: DEFAULT_TABLE.to_string(),
+        }
+    }
+
+    /// Table used to store the entries. The name is put into the queries as is, so it
+    /// must be a trusted identifier.
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.table = table.into();
+        self
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Creates the cache table if it does not exist yet.
+    pub async fn migrate(&self) -> eyre::Result<()> {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                key TEXT PRIMARY KEY,
+                value JSONB NOT NULL,
+                expires_at TIMESTAMPTZ NOT NULL
+            )",
+            table = self.table
+        ))
+        .execute(&self.pool)
+        .await
+        .context("failed to create cache table")?;
+
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS {table}_expires_at_idx ON {table} (expires_at)",
+            table = self.table
+        ))
+        .execute(&self.pool)
+        .await
+        .context("failed to create cache index")?;
+
+        Ok(())
+    }
+
+    /// Deletes expired rows and returns how many were removed.
+    pub async fn reap_expired(&self) -> eyre::Result<u64> {
+        let result = sqlx::query(&format!(
+            "DELETE FROM {table} WHERE expires_at <= now()",
+            table = self.table
+        ))
+        .execute(&self.pool)
+        .await
+        .context("failed to delete expired cache entries")?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Reaps expired rows every `interval` until `cancel_token` is cancelled.
+    pub async fn run_reaper(self, interval: Duration, cancel_token: CancellationToken) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = cancel_token.cancelled() => {
+                    #[cfg(feature = "log")]
+                    log::info!(client = "PgCache"; "reaper stopped");
+
+                    return;
+                }
+            }
+
+            match self.reap_expired().await {
+                Ok(_reaped) => {
+                    #[cfg(feature = "log")]
+                    log::debug!("[cache] reaped {_reaped} expired rows");
+                }
+                Err(_err) => {
+                    #[cfg(feature = "log")]
+                    log::error!(client = "PgCache"; "failed to reap expired rows: {_err:?}");
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for PgCache {
+    async fn get_raw(&self, key: &str) -> Option<String> {
+        self.get_raw_with_ttl(key).await.map(|(value, _)| value)
+    }
+
+    async fn get_raw_with_ttl(&self, key: &str) -> Option<(String, Option<Duration>)> {
+        let result = sqlx::query_as::<_, (String, i64)>(&format!(
+            "SELECT value::text, (EXTRACT(EPOCH FROM expires_at - now()) * 1000)::bigint
+            FROM {table}
+            WHERE key = $1 AND expires_at > now()",
+            table = self.table
+        ))
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await;
+
+        match result {
+            Ok(row) => row.map(|(value, ttl_ms)| {
+                let ttl = Duration::from_millis(ttl_ms.max(0) as u64);
+                (value, Some(ttl))
+            }),
+            Err(_err) => {
+                #[cfg(feature = "log")]
+                log::warn!("[cache] postgres get failed key={key}: {_err:?}");
+
+                None
+            }
+        }
+    }
+
+    async fn set_raw(&self, key: String, value: String, expires_in: Option<Duration>) {
+        let expires_in = expires_in.unwrap_or(DEFAULT_TTL);
+
+        let result = sqlx::query(&format!(
+            "INSERT INTO {table} (key, value, expires_at)
+            VALUES ($1, $2::jsonb, now() + $3 * interval '1 millisecond')
+            ON CONFLICT (key) DO UPDATE
+            SET value = EXCLUDED.value, expires_at = EXCLUDED.expires_at",
+            table = self.table
+        ))
+        .bind(&key)
+        .bind(value)
+        .bind(expires_in.as_millis() as i64)
+        .execute(&self.pool)
+        .await;
+
+        if let Err(_err) = result {
+            #[cfg(feature = "log")]
+            log::warn!("[cache] postgres set failed key={key}: {_err:?}");
+        }
+    }
+
+    async fn remove_raw(&self, key: &str) -> bool {
+        let result = sqlx::query(&format!(
+            "DELETE FROM {table} WHERE key = $1",
+            table = self.table
+        ))
+        .bind(key)
+        .execute(&self.pool)
+        .await;
+
+        match result {
+            Ok(result) => result.rows_affected() > 0,
+            Err(_err) => {
+                #[cfg(feature = "log")]
+                log::warn!("[cache] postgres remove failed key={key}: {_err:?}");
+
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{MemCache, TieredCache};
+
+    async fn connect(table: &str) -> PgCache {
+        let url = std::env::var("DATABASE_URL")
+            .unwrap_or("postgres://postgres@127.0.0.1/postgres".to_string());
+        let pool = PgPool::connect(&url).await.unwrap();
+
+        let cache = PgCache::new(pool).with_table(table);
+        sqlx::query(&format!("DROP TABLE IF EXISTS {table}"))
+            .execute(cache.pool())
+            .await
+            .unwrap();
+        cache.migrate().await.unwrap();
+        cache
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local postgres"]
+    async fn test_get_set() {
+        let cache = connect("solar_cache_test_get_set").await;
+
+        assert_eq!(cache.set(&"a", vec![1, 2], None).await, Some(vec![1, 2]));
+        assert_eq!(cache.get::<_, Vec<i32>>(&"a").await, Some(vec![1, 2]));
+        assert_eq!(cache.get::<_, Vec<i32>>(&"missing").await, None);
+
+        cache.set(&"a", vec![3], None).await;
+        assert_eq!(cache.get::<_, Vec<i32>>(&"a").await, Some(vec![3]));
+
+        let (_, ttl) = cache.get_raw_with_ttl("\"a\"").await.unwrap();
+        assert!(ttl.unwrap() <= DEFAULT_TTL);
+
+        assert!(cache.remove(&"a").await);
+        assert!(!cache.remove(&"a").await);
+        assert_eq!(cache.get::<_, Vec<i32>>(&"a").await, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local postgres"]
+    async fn test_reap_expired() {
+        let cache = connect("solar_cache_test_reap").await;
+
+        cache.set(&"a", 1, Some(Duration::from_millis(50))).await;
+        cache.set(&"b", 2, None).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(cache.get::<_, i32>(&"a").await, None);
+        assert_eq!(cache.reap_expired().await.unwrap(), 1);
+        assert_eq!(cache.get::<_, i32>(&"b").await, Some(2));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local postgres"]
+    async fn test_warm_restart() {
+        let l2 = connect("solar_cache_test_tiered").await;
+
+        let cache = TieredCache::new(MemCache::new(), l2.clone());
+        cache.set(&"a", 1, None).await;
+
+        // a fresh process only has an empty L1
+        let restarted = TieredCache::new(MemCache::new(), l2);
+        let value = restarted
+            .cached(|| async { Err("not called".to_string()) }, "a", None)
+            .await;
+        assert_eq!(value, Ok(1));
+        assert_eq!(restarted.l1().get::<_, i32>(&"a").await, Some(1));
+    }
+}
@@ -0,0 +1,265 @@
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use tokio_util::sync::CancellationToken;
+
+use super::{CacheBackend, MemCache};
+
+const DEFAULT_CHANNEL: &str = "solar_cache_invalidation";
+
+/// Invalidation broadcast to every replica, sent as the JSON payload of a `NOTIFY`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Invalidation {
+    /// Serialized key, as stored by [`MemCache`].
+    Key {
+        key: String,
+    },
+    Tag {
+        tag: String,
+    },
+    Prefix {
+        prefix: String,
+    },
+    Clear,
+}
+
+impl Invalidation {
+    pub fn key<K: Serialize>(key: &K) -> eyre::Result<Self> {
+        let key = serde_json::to_string(key).context("failed to serialize cache key")?;
+        Ok(Self::Key { key })
+    }
+
+    /// Applies the invalidation to the local cache and returns how many entries it removed.
+    pub async fn apply(&self, cache: &MemCache) -> usize {
+        match self {
+            Invalidation::Key { key } => cache.remove_raw(key).await as usize,
+            Invalidation::Tag { tag } => cache.invalidate_tag(tag).await,
+            Invalidation::Prefix { prefix } => cache.invalidate_prefix(prefix).await,
+            Invalidation::Clear => {
+                let removed = cache.len().await;
+                cache.clear().await;
+                removed
+            }
+        }
+    }
+}
+
+/// Publishes cache invalidations over Postgres `NOTIFY` and applies the ones published by
+/// any replica to the local [`MemCache`], so that an update on one replica does not leave
+/// the others serving the old value until it expires.
+///
+/// Invalidations are delivered to the publishing replica's listener as well, so callers only
+/// publish and do not have to touch their own cache.
+///
+/// Example
+/// ```rust,no_run
+/// use solar::cache::{MemCache, PgInvalidationBus};
+/// use solar::trx_factory::SqlxTrxFactory;
+/// use tokio_util::sync::CancellationToken;
+///
+/// #[tokio::main]
+/// async fn main() -> eyre::Result<()> {
+///     let pool = sqlx::PgPool::connect("postgres://localhost/solar").await?;
+///     let trx_factory = SqlxTrxFactory::new(pool);
+///     let cache = MemCache::new();
+///     let bus = PgInvalidationBus::new(trx_factory.pool().clone());
+///
+///     tokio::spawn(bus.clone().run_listener(cache.clone(), CancellationToken::new()));
+///
+///     bus.invalidate_key(&("wallet_settings", "9xQe...")).await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PgInvalidationBus {
+    pool: PgPool,
+    channel: String,
+}
+
+impl PgInvalidationBus {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            channel: DEFAULT_CHANNEL.to_string(),
+        }
+    }
+
+    /// Channel the invalidations are sent on. Replicas sharing a database but not a cache
+    /// should use different channels.
+    pub fn with_channel(mut self, channel: impl Into<String>) -> Self {
+        self.channel = channel.into();
+        self
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub async fn publish(&self, invalidation: &Invalidation) -> eyre::Result<()> {
+        let payload =
+            serde_json::to_string(invalidation).context("failed to serialize invalidation")?;
+
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(&self.channel)
+            .bind(payload)
+            .execute(&self.pool)
+            .await
+            .context("failed to publish invalidation")?;
+
+        Ok(())
+    }
+
+    pub async fn invalidate_key<K: Serialize>(&self, key: &K) -> eyre::Result<()> {
+        self.publish(&Invalidation::key(key)?).await
+    }
+
+    pub async fn invalidate_tag(&self, tag: &str) -> eyre::Result<()> {
+        self.publish(&Invalidation::Tag {
+            tag: tag.to_string(),
+        })
+        .await
+    }
+
+    pub async fn invalidate_prefix(&self, prefix: &str) -> eyre::Result<()> {
+        self.publish(&Invalidation::Prefix {
+            prefix: prefix.to_string(),
+        })
+        .await
+    }
+
+    pub async fn clear(&self) -> eyre::Result<()> {
+        self.publish(&Invalidation::Clear).await
+    }
+
+    /// Applies published invalidations to `cache` until `cancel_token` is cancelled.
+    ///
+    /// Notifications sent while the listening connection is down are lost, so the local
+    /// cache is cleared whenever it has to reconnect.
+    pub async fn run_listener(
+        self,
+        cache: MemCache,
+        cancel_token: CancellationToken,
+    ) -> eyre::Result<()> {
+        let mut listener = PgListener::connect_with(&self.pool)
+            .await
+            .context("failed to connect invalidation listener")?;
+        listener
+            .listen(&self.channel)
+            .await
+            .context("failed to listen for invalidations")?;
+
+        loop {
+            let notification = tokio::select! {
+                notification = listener.try_recv() => notification,
+                _ = cancel_token.cancelled() => {
+                    #[cfg(feature = "log")]
+                    log::info!(client = "PgInvalidationBus"; "listener stopped");
+
+                    return Ok(());
+                }
+            };
+
+            match notification {
+                Ok(Some(notification)) => {
+                    match serde_json::from_str::<Invalidation>(notification.payload()) {
+                        Ok(invalidation) => {
+                            let _removed = invalidation.apply(&cache).await;
+
+                            #[cfg(feature = "log")]
+                            log::debug!("[cache] {invalidation:?} removed {_removed} entries");
+                        }
+                        Err(_err) => {
+                            #[cfg(feature = "log")]
+                            log::warn!("[cache] invalid invalidation payload: {_err:?}");
+                        }
+                    }
+                }
+                Ok(None) => {
+                    #[cfg(feature = "log")]
+                    log::warn!(client = "PgInvalidationBus"; "connection lost, clearing cache");
+
+                    cache.clear().await;
+                }
+                Err(_err) => {
+                    #[cfg(feature = "log")]
+                    log::error!(client = "PgInvalidationBus"; "failed to receive: {_err:?}");
+
+                    cache.clear().await;
+                    tokio::select! {
+                        _ = tokio::time::sleep(std::time::Duration::from_secs(1)) => {}
+                        _ = cancel_token.cancelled() => return Ok(()),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_payload_format() {
+        let invalidation = Invalidation::key(&("wallet", "abc")).unwrap();
+        let payload = serde_json::to_string(&invalidation).unwrap();
+
+        assert_eq!(payload, r#"{"type":"key","key":"[\"wallet\",\"abc\"]"}"#);
+        assert_eq!(
+            serde_json::from_str::<Invalidation>(&payload).unwrap(),
+            invalidation
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let cache = MemCache::new();
+        cache.set(&"a", 1, None).await;
+        cache.set_with_tags(&"b", 2, None, &["wallet"]).await;
+
+        assert_eq!(Invalidation::key(&"a").unwrap().apply(&cache).await, 1);
+        let tag = Invalidation::Tag {
+            tag: "wallet".to_string(),
+        };
+        assert_eq!(tag.apply(&cache).await, 1);
+        assert!(cache.is_empty().await);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local postgres"]
+    async fn test_invalidation_reaches_every_replica() {
+        let url = std::env::var("DATABASE_URL")
+            .unwrap_or("postgres://postgres@127.0.0.1/postgres".to_string());
+        let pool = PgPool::connect(&url).await.unwrap();
+        let bus = PgInvalidationBus::new(pool).with_channel("solar_cache_test_invalidation");
+        let cancel_token = CancellationToken::new();
+
+        let replicas = [MemCache::new(), MemCache::new()];
+        for cache in &replicas {
+            cache.set(&"wallet", 1, None).await;
+            tokio::spawn(
+                bus.clone()
+                    .run_listener(cache.clone(), cancel_token.clone()),
+            );
+        }
+        // give the listeners time to subscribe
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        bus.invalidate_key(&"wallet").await.unwrap();
+
+        for cache in &replicas {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while cache.get::<_, i32>(&"wallet").await.is_some() {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+        }
+        cancel_token.cancel();
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use eyre::Context;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+
+use super::{CacheBackend, DEFAULT_TTL};
+
+/// [`CacheBackend`] stored in Redis, shared by every process connected to the same server.
+///
+/// Failing commands are treated as cache misses, so an unavailable Redis degrades to
+/// calling the loader instead of failing the request.
+///
+/// Example
+/// ```rust,no_run
+/// use solar::cache::{CacheBackend, RedisCache};
+///
+/// #[tokio::main]
+/// async fn main() -> eyre::Result<()> {
+///     let cache = RedisCache::connect("redis://127.0.0.1/").await?;
+///     cache.set(&"sol_usd", 150.0, None).await;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RedisCache {
+    connection: ConnectionManager,
+}
+
+impl RedisCache {
+    pub async fn connect(url: &str) -> eyre::Result<Self> {
+        let client = redis::Client::open(url).context("invalid redis url")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("failed to connect to redis")?;
+
+        Ok(Self::new(connection))
+    }
+
+    pub fn new(connection: ConnectionManager) -> Self {
+        Self { connection }
+    }
+
+    pub fn connection(&self) -> &ConnectionManager {
+        &self.connection
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for RedisCache {
+    async fn get_raw(&self, key: &str) -> Option<String> {
+        let mut connection = self.connection.clone();
+        match connection.get::<_, Option<String>>(key).await {
+            Ok(value) => value,
+            Err(_err) => {
+                #[cfg(feature = "log")]
+                log::warn!("[cache] redis get failed key={key}: {_err:?}");
+
+                None
+            }
+        }
+    }
+
+    async fn get_raw_with_ttl(&self, key: &str) -> Option<(String, Option<Duration>)> {
+        let mut connection = self.connection.clone();
+        let result = redis::pipe()
+            .get(key)
+            .pttl(key)
+            .query_async::<(Option<String>, i64)>(&mut connection)
+            .await;
+
+        match result {
+            // PTTL is negative when the key has no expiry
+            Ok((value, ttl_ms)) => {
+                let ttl = u64::try_from(ttl_ms).ok().map(Duration::from_millis);
+                value.map(|value| (value, ttl))
+            }
+            Err(_err) => {
+                #[cfg(feature = "log")]
+                log::warn!("[cache] redis get failed key={key}: {_err:?}");
+
+                None
+            }
+        }
+    }
+
+    async fn set_raw(&self, key: String, value: String, expires_in: Option<Duration>) {
+        let expires_in = expires_in.unwrap_or(DEFAULT_TTL);
+        // PSETEX rejects a zero expiry
+        let expires_in_ms = (expires_in.as_millis() as u64).max(1);
+
+        let mut connection = self.connection.clone();
+        if let Err(_err) = connection
+            .pset_ex::<_, _, ()>(&key, value, expires_in_ms)
+            .await
+        {
+            #[cfg(feature = "log")]
+            log::warn!("[cache] redis set failed key={key}: {_err:?}");
+        }
+    }
+
+    async fn remove_raw(&self, key: &str) -> bool {
+        let mut connection = self.connection.clone();
+        match connection.del::<_, u64>(key).await {
+            Ok(removed) => removed > 0,
+            Err(_err) => {
+                #[cfg(feature = "log")]
+                log::warn!("[cache] redis remove failed key={key}: {_err:?}");
+
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connect() -> RedisCache {
+        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1/".to_string());
+        RedisCache::connect(&url).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_get_set() {
+        let cache = connect().await;
+
+        assert_eq!(cache.set(&"solar:test:a", 1, None).await, Some(1));
+        assert_eq!(cache.get::<_, i32>(&"solar:test:a").await, Some(1));
+        assert_eq!(cache.get::<_, i32>(&"solar:test:missing").await, None);
+
+        let (value, ttl) = cache.get_raw_with_ttl("\"solar:test:a\"").await.unwrap();
+        assert_eq!(value, "1");
+        assert!(ttl.unwrap() <= DEFAULT_TTL);
+
+        assert!(cache.remove(&"solar:test:a").await);
+        assert_eq!(cache.get::<_, i32>(&"solar:test:a").await, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_expiry() {
+        let cache = connect().await;
+
+        cache
+            .set(&"solar:test:b", 1, Some(Duration::from_millis(100)))
+            .await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(cache.get::<_, i32>(&"solar:test:b").await, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_cached() {
+        let cache = connect().await;
+
+        let value = cache
+            .cached(|| async { Ok::<_, String>(7) }, "solar:test:c", None)
+            .await;
+        assert_eq!(value, Ok(7));
+
+        let value = cache
+            .cached(
+                || async { Err("not called".to_string()) },
+                "solar:test:c",
+                None,
+            )
+            .await;
+        assert_eq!(value, Ok(7));
+    }
+}
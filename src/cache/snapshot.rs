@@ -0,0 +1,241 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use super::{Data, MemCache};
+
+/// Entry as written to disk. `Instant`s only mean something inside the running process,
+/// so deadlines are stored as milliseconds since the unix epoch.
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotEntry {
+    key: String,
+    value: String,
+    expires_at_ms: Option<u64>,
+    stale_at_ms: Option<u64>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+fn to_unix_ms(instant: Instant, now: Instant, wall_now: SystemTime) -> Option<u64> {
+    let wall = wall_now + instant.saturating_duration_since(now);
+    let since_epoch = wall.duration_since(UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_millis() as u64)
+}
+
+/// Time left until `unix_ms`, `None` once it has passed.
+fn remaining(unix_ms: u64, wall_now: SystemTime) -> Option<Duration> {
+    let wall = UNIX_EPOCH + Duration::from_millis(unix_ms);
+    wall.duration_since(wall_now)
+        .ok()
+        .filter(|remaining| !remaining.is_zero())
+}
+
+impl MemCache {
+    /// Writes every entry that has not expired yet to `path` and returns how many were
+    /// written. The file is replaced atomically, so a crash mid-write keeps the previous
+    /// snapshot.
+    pub async fn snapshot(&self, path: impl AsRef<Path>) -> eyre::Result<usize> {
+        let path = path.as_ref();
+        let now = Instant::now();
+        let wall_now = SystemTime::now();
+
+        let entries: Vec<SnapshotEntry> = self
+            .entries()
+            .await
+            .into_iter()
+            .map(|(key, data)| SnapshotEntry {
+                key,
+                value: data.value,
+                expires_at_ms: data
+                    .expires_at
+                    .and_then(|expires_at| to_unix_ms(expires_at, now, wall_now)),
+                stale_at_ms: data
+                    .stale_at
+                    .and_then(|stale_at| to_unix_ms(stale_at, now, wall_now)),
+                tags: data.tags,
+            })
+            .collect();
+
+        let json = serde_json::to_vec(&entries).context("failed to serialize cache snapshot")?;
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        tokio::fs::write(&tmp_path, json)
+            .await
+            .with_context(|| format!("failed to write cache snapshot to {tmp_path:?}"))?;
+        tokio::fs::rename(&tmp_path, path)
+            .await
+            .with_context(|| format!("failed to move cache snapshot to {path:?}"))?;
+
+        Ok(entries.len())
+    }
+
+    /// Loads the entries of a snapshot written by [`MemCache::snapshot`] with the time they
+    /// had left when it was taken, skipping those that expired since. Returns how many were
+    /// restored. A missing file restores nothing.
+    pub async fn restore(&self, path: impl AsRef<Path>) -> eyre::Result<usize> {
+        let path = path.as_ref();
+        let json = match tokio::fs::read(path).await {
+            Ok(json) => json,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read cache snapshot from {path:?}"));
+            }
+        };
+        let entries: Vec<SnapshotEntry> =
+            serde_json::from_slice(&json).context("failed to parse cache snapshot")?;
+
+        let wall_now = SystemTime::now();
+        let mut restored = 0;
+        for entry in entries {
+            let expires_in = match entry.expires_at_ms {
+                Some(expires_at_ms) => match remaining(expires_at_ms, wall_now) {
+                    Some(expires_in) => Some(expires_in),
+                    None => continue,
+                },
+                None => None,
+            };
+            let stale_in = entry
+                .stale_at_ms
+                .map(|stale_at_ms| remaining(stale_at_ms, wall_now).unwrap_or_default());
+
+            let data = Data::new(entry.value, expires_in)
+                .with_stale_in(stale_in)
+                .with_tags(entry.tags);
+            self.insert_entry(entry.key, data).await;
+            restored += 1;
+        }
+
+        #[cfg(feature = "log")]
+        log::info!("[cache] restored {restored} entries from {path:?}");
+
+        Ok(restored)
+    }
+
+    /// Snapshots the cache to `path` every `interval` and one last time once `cancel_token`
+    /// is cancelled, so that a graceful shutdown leaves an up to date snapshot behind.
+    pub async fn run_snapshots(
+        self,
+        path: PathBuf,
+        interval: Duration,
+        cancel_token: CancellationToken,
+    ) {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await;
+
+        loop {
+            let cancelled = tokio::select! {
+                _ = interval.tick() => false,
+                _ = cancel_token.cancelled() => true,
+            };
+
+            match self.snapshot(&path).await {
+                Ok(_written) => {
+                    #[cfg(feature = "log")]
+                    log::debug!("[cache] wrote {_written} entries to {path:?}");
+                }
+                Err(_err) => {
+                    #[cfg(feature = "log")]
+                    log::error!("[cache] failed to snapshot cache: {_err:?}");
+                }
+            }
+
+            if cancelled {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheBackend;
+
+    fn snapshot_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("solar_{name}_{}.json", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_restore() {
+        let path = snapshot_path("snapshot_and_restore");
+
+        let cache = MemCache::new();
+        cache.set(&"a", 1, Some(Duration::from_secs(60))).await;
+        cache.set_with_tags(&"b", vec![2, 3], None, &["pool"]).await;
+        cache.set(&"c", 4, Some(Duration::from_millis(1))).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(cache.snapshot(&path).await.unwrap(), 2);
+
+        let restored = MemCache::new();
+        assert_eq!(restored.restore(&path).await.unwrap(), 2);
+        assert_eq!(restored.get::<_, i32>(&"a").await, Some(1));
+        assert_eq!(restored.get::<_, Vec<i32>>(&"b").await, Some(vec![2, 3]));
+        assert_eq!(restored.get::<_, i32>(&"c").await, None);
+
+        let (_, ttl) = restored.get_raw_with_ttl("\"a\"").await.unwrap();
+        let ttl = ttl.unwrap();
+        assert!(ttl <= Duration::from_secs(60) && ttl > Duration::from_secs(55));
+
+        assert_eq!(restored.invalidate_tag("pool").await, 1);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore_missing_file() {
+        let cache = MemCache::new();
+        let restored = cache.restore(snapshot_path("missing")).await.unwrap();
+
+        assert_eq!(restored, 0);
+    }
+
+    #[tokio::test]
+    async fn test_restore_skips_expired() {
+        let path = snapshot_path("restore_skips_expired");
+        let past = SystemTime::now() - Duration::from_secs(1);
+        let entries = vec![SnapshotEntry {
+            key: "\"a\"".to_string(),
+            value: "1".to_string(),
+            expires_at_ms: Some(past.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64),
+            stale_at_ms: None,
+            tags: Vec::new(),
+        }];
+        tokio::fs::write(&path, serde_json::to_vec(&entries).unwrap())
+            .await
+            .unwrap();
+
+        let cache = MemCache::new();
+        assert_eq!(cache.restore(&path).await.unwrap(), 0);
+        assert!(cache.is_empty().await);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_snapshots_writes_on_shutdown() {
+        let path = snapshot_path("run_snapshots");
+        let cache = MemCache::new();
+        cache.set(&"a", 1, None).await;
+
+        let cancel_token = CancellationToken::new();
+        let task = tokio::spawn(cache.clone().run_snapshots(
+            path.clone(),
+            Duration::from_secs(3600),
+            cancel_token.clone(),
+        ));
+        cancel_token.cancel();
+        task.await.unwrap();
+
+        let restored = MemCache::new();
+        assert_eq!(restored.restore(&path).await.unwrap(), 1);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Point-in-time counters of a cache, see [`MemCache::stats`](super::MemCache::stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub insertions: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+    pub loader_errors: u64,
+    /// Number of entries currently stored.
+    pub entries: u64,
+    /// Total size of the serialized values currently stored.
+    pub bytes: u64,
+}
+
+impl CacheStats {
+    /// Share of lookups that were served from the cache, `0.0` before the first lookup.
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+
+        self.hits as f64 / lookups as f64
+    }
+
+    /// Publishes the snapshot through the `metrics` facade, labelled with `cache`.
+    /// Meant to be called periodically, e.g. right before the exporter is scraped.
+    #[cfg(feature = "metrics")]
+    pub fn record(&self, cache: &str) {
+        let labels = [("cache", cache.to_string())];
+
+        metrics::counter!("solar_cache_hits_total", &labels).absolute(self.hits);
+        metrics::counter!("solar_cache_misses_total", &labels).absolute(self.misses);
+        metrics::counter!("solar_cache_insertions_total", &labels).absolute(self.insertions);
+        metrics::counter!("solar_cache_evictions_total", &labels).absolute(self.evictions);
+        metrics::counter!("solar_cache_expirations_total", &labels).absolute(self.expirations);
+        metrics::counter!("solar_cache_loader_errors_total", &labels).absolute(self.loader_errors);
+        metrics::gauge!("solar_cache_entries", &labels).set(self.entries as f64);
+        metrics::gauge!("solar_cache_bytes", &labels).set(self.bytes as f64);
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounter {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+    loader_errors: AtomicU64,
+}
+
+impl StatsCounter {
+    pub(crate) fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn insertion(&self) {
+        self.insertions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn evictions(&self, count: usize) {
+        self.evictions.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn expirations(&self, count: usize) {
+        self.expirations.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn loader_error(&self) {
+        self.loader_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, entries: usize, bytes: usize) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            insertions: self.insertions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+            loader_errors: self.loader_errors.load(Ordering::Relaxed),
+            entries: entries as u64,
+            bytes: bytes as u64,
+        }
+    }
+}
@@ -0,0 +1,635 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use tokio::sync::{Notify, RwLock};
+use tokio::time::{Instant, sleep_until};
+use tokio_util::sync::CancellationToken;
+
+use super::stats::StatsCounter;
+use super::{CacheStats, DEFAULT_TTL};
+
+/// Entry of a cache with its expiry.
+///
+/// The fields used to be public with a `std::time::Instant` expiry. They are read through
+/// the accessors now, and the instants are [`tokio::time::Instant`]s so that the cache
+/// follows a paused tokio clock, convertible with [`Instant::into_std`].
+#[derive(Debug)]
+pub struct Data<V = String> {
+    pub(crate) value: V,
+    pub(crate) expires_at: Option<Instant>,
+    pub(crate) stale_at: Option<Instant>,
+    pub(crate) tags: Vec<String>,
+    // updated by readers under the read lock
+    hits: AtomicU64,
+    last_access: AtomicU64,
+    // position in `Storage::order`, which may lag behind the two counters above
+    order_key: (u64, u64),
+    // tick of the insertion, tells apart entries expiring at the same instant
+    inserted_at: u64,
+}
+
+impl<V: Clone> Clone for Data<V> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            expires_at: self.expires_at,
+            stale_at: self.stale_at,
+            tags: self.tags.clone(),
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+            last_access: AtomicU64::new(self.last_access.load(Ordering::Relaxed)),
+            order_key: self.order_key,
+            inserted_at: self.inserted_at,
+        }
+    }
+}
+
+impl<V> Data<V> {
+    /// Entry expiring after `expires_in`, or after the default TTL of an hour.
+    pub fn new(value: V, expires_in: Option<Duration>) -> Self {
+        let expires_in = expires_in.unwrap_or(DEFAULT_TTL);
+
+        Self {
+            value,
+            expires_at: Some(Instant::now() + expires_in),
+            stale_at: None,
+            tags: Vec::new(),
+            hits: AtomicU64::new(0),
+            last_access: AtomicU64::new(0),
+            order_key: (0, 0),
+            inserted_at: 0,
+        }
+    }
+
+    pub(crate) fn with_stale_in(mut self, stale_in: Option<Duration>) -> Self {
+        self.stale_at = stale_in.map(|stale_in| Instant::now() + stale_in);
+        self
+    }
+
+    pub(crate) fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub(crate) fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    pub fn into_value(self) -> V {
+        self.value
+    }
+
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.expires_at
+    }
+
+    /// When the entry is due for a refresh, see
+    /// [`MemCache::cached_stale_while_revalidate`](super::MemCache::cached_stale_while_revalidate).
+    pub fn stale_at(&self) -> Option<Instant> {
+        self.stale_at
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+}
+
+/// Which entry gets dropped first once a bounded cache is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Least recently used entry is evicted first.
+    #[default]
+    Lru,
+    /// Least frequently used entry is evicted first, ties are broken by recency.
+    Lfu,
+}
+
+impl EvictionPolicy {
+    fn order_key<V>(&self, data: &Data<V>) -> (u64, u64) {
+        let last_access = data.last_access.load(Ordering::Relaxed);
+        match self {
+            EvictionPolicy::Lru => (0, last_access),
+            EvictionPolicy::Lfu => (data.hits.load(Ordering::Relaxed), last_access),
+        }
+    }
+}
+
+/// Why an entry left the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RemovalCause {
+    /// Its TTL ran out.
+    Expired,
+    /// It was dropped to make room for another entry.
+    Evicted,
+    /// A new value was stored under the same key.
+    Replaced,
+    /// It was removed, invalidated or cleared explicitly.
+    Removed,
+}
+
+/// Entry that left the cache, passed to the removal listeners.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Removal<K = String> {
+    pub key: K,
+    pub cause: RemovalCause,
+}
+
+/// Listener registered on a [`Store`], unregistered once it returns `false`.
+type Listener<K> = Box<dyn Fn(&Removal<K>) -> bool + Send + Sync>;
+
+/// Capacity of a [`Store`]. `weigher` gives the size of a value counted against `max_bytes`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits<V> {
+    pub(crate) max_entries: Option<usize>,
+    pub(crate) max_bytes: Option<usize>,
+    pub(crate) policy: EvictionPolicy,
+    pub(crate) weigher: fn(&V) -> usize,
+}
+
+#[derive(Debug)]
+struct Storage<K, V> {
+    entries: HashMap<K, Data<V>>,
+    // (frequency, last access) -> key, ordered by eviction priority as of the last write
+    order: BTreeMap<(u64, u64), K>,
+    expiries: BTreeMap<(Instant, u64), K>,
+    tags: HashMap<String, HashSet<K>>,
+    bytes: usize,
+    tick: AtomicU64,
+    limits: Limits<V>,
+    // only filled while a listener is registered, drained by the `Store` after every write
+    removals: Vec<Removal<K>>,
+    track_removals: bool,
+}
+
+impl<K: Hash + Eq + Clone, V> Storage<K, V> {
+    fn new(limits: Limits<V>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            expiries: BTreeMap::new(),
+            tags: HashMap::new(),
+            bytes: 0,
+            tick: AtomicU64::new(0),
+            limits,
+            removals: Vec::new(),
+            track_removals: false,
+        }
+    }
+
+    fn next_tick(&self) -> u64 {
+        self.tick.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn is_bounded(&self) -> bool {
+        self.limits.max_entries.is_some() || self.limits.max_bytes.is_some()
+    }
+
+    /// Only needs shared access: the access is recorded in the counters of the entry, and
+    /// the eviction order catches up with them when an entry is evicted.
+    fn get<Q>(&self, key: &Q) -> Option<&Data<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let data = self.entries.get(key)?;
+        if self.is_bounded() {
+            data.hits.fetch_add(1, Ordering::Relaxed);
+            data.last_access.store(self.next_tick(), Ordering::Relaxed);
+        }
+
+        Some(data)
+    }
+
+    /// Key of the entry to evict first, moving the entries read since they were ordered
+    /// to their current position on the way.
+    fn eviction_candidate(&mut self) -> Option<K> {
+        let policy = self.limits.policy;
+        loop {
+            let (&order_key, key) = self.order.first_key_value()?;
+            let data = self.entries.get_mut(key)?;
+
+            let current = policy.order_key(data);
+            if current == order_key {
+                return Some(key.clone());
+            }
+
+            // counters only grow, so every entry moves back at most once
+            data.order_key = current;
+            let key = self.order.remove(&order_key)?;
+            self.order.insert(current, key);
+        }
+    }
+
+    /// Returns how many entries were evicted to make room, or `None` if the value is larger
+    /// than the whole cache and was not stored, the previous value of `key` being kept.
+    fn insert(&mut self, key: K, mut data: Data<V>) -> Option<usize> {
+        let size = (self.limits.weigher)(&data.value);
+        if self
+            .limits
+            .max_bytes
+            .is_some_and(|max_bytes| size > max_bytes)
+        {
+            return None;
+        }
+
+        let hits = match self.remove(&key, RemovalCause::Replaced) {
+            Some(previous) => previous.hits.into_inner() + 1,
+            None => 1,
+        };
+
+        let mut evicted = 0;
+        while self.is_full(size) {
+            let Some(key) = self.eviction_candidate() else {
+                break;
+            };
+            self.remove(&key, RemovalCause::Evicted);
+            evicted += 1;
+        }
+
+        let tick = self.next_tick();
+        data.hits = AtomicU64::new(hits);
+        data.last_access = AtomicU64::new(tick);
+        data.inserted_at = tick;
+        data.order_key = self.limits.policy.order_key(&data);
+        self.bytes += size;
+        self.order.insert(data.order_key, key.clone());
+        if let Some(expires_at) = data.expires_at {
+            self.expiries.insert((expires_at, tick), key.clone());
+        }
+        for tag in &data.tags {
+            self.tags
+                .entry(tag.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        self.entries.insert(key, data);
+
+        Some(evicted)
+    }
+
+    fn remove<Q>(&mut self, key: &Q, cause: RemovalCause) -> Option<Data<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (owned_key, data) = self.entries.remove_entry(key)?;
+        self.order.remove(&data.order_key);
+        if let Some(expires_at) = data.expires_at {
+            self.expiries.remove(&(expires_at, data.inserted_at));
+        }
+        for tag in &data.tags {
+            if let Some(keys) = self.tags.get_mut(tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.tags.remove(tag);
+                }
+            }
+        }
+        self.bytes -= (self.limits.weigher)(&data.value);
+        if self.track_removals {
+            self.removals.push(Removal {
+                key: owned_key,
+                cause,
+            });
+        }
+        Some(data)
+    }
+
+    fn remove_tag(&mut self, tag: &str) -> usize {
+        let keys = self.tags.remove(tag).unwrap_or_default();
+        for key in &keys {
+            self.remove(key, RemovalCause::Removed);
+        }
+        keys.len()
+    }
+
+    fn clear(&mut self) {
+        if self.track_removals {
+            let removals = self.entries.drain().map(|(key, _)| Removal {
+                key,
+                cause: RemovalCause::Removed,
+            });
+            self.removals.extend(removals);
+        }
+        self.entries.clear();
+        self.order.clear();
+        self.expiries.clear();
+        self.tags.clear();
+        self.bytes = 0;
+    }
+
+    fn next_expiry(&self) -> Option<Instant> {
+        self.expiries
+            .first_key_value()
+            .map(|((expires_at, _), _)| *expires_at)
+    }
+
+    /// Removes every entry whose `expires_at` is not after `now`.
+    fn remove_expired(&mut self, now: Instant) -> usize {
+        let mut removed = 0;
+        while self
+            .next_expiry()
+            .is_some_and(|expires_at| expires_at <= now)
+        {
+            let Some((_, key)) = self.expiries.pop_first() else {
+                break;
+            };
+            self.remove(&key, RemovalCause::Expired);
+            removed += 1;
+        }
+        removed
+    }
+
+    fn is_full(&self, incoming_bytes: usize) -> bool {
+        let entries_full = self
+            .limits
+            .max_entries
+            .is_some_and(|max_entries| self.entries.len() >= max_entries);
+        let bytes_full = self
+            .limits
+            .max_bytes
+            .is_some_and(|max_bytes| self.bytes + incoming_bytes > max_bytes);
+
+        !self.entries.is_empty() && (entries_full || bytes_full)
+    }
+}
+
+impl<V> Storage<String, V> {
+    /// Number of entries and bytes stored under keys starting with `prefix`.
+    fn prefix_usage(&self, prefix: &str) -> (usize, usize) {
+        self.entries
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .fold((0, 0), |(entries, bytes), (_, data)| {
+                (entries + 1, bytes + (self.limits.weigher)(&data.value))
+            })
+    }
+
+    fn remove_prefix(&mut self, prefix: &str) -> usize {
+        let keys: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+
+        for key in &keys {
+            self.remove(key.as_str(), RemovalCause::Removed);
+        }
+        keys.len()
+    }
+}
+
+/// Entries, eviction, expiry and statistics shared by [`MemCache`](super::MemCache) and
+/// [`TypedCache`](super::TypedCache), which only differ in what they store as `V`.
+pub(crate) struct Store<K, V> {
+    storage: RwLock<Storage<K, V>>,
+    listeners: Mutex<Vec<Listener<K>>>,
+    has_listeners: AtomicBool,
+    pub(crate) stats: StatsCounter,
+    expiry_changed: Arc<Notify>,
+    sweeper_started: AtomicBool,
+    cancel_token: CancellationToken,
+}
+
+impl<K: std::fmt::Debug, V: std::fmt::Debug> std::fmt::Debug for Store<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Store")
+            .field("storage", &self.storage)
+            .field("listeners", &self.listeners.lock().unwrap().len())
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K, V> Drop for Store<K, V> {
+    fn drop(&mut self) {
+        self.cancel_token.cancel();
+    }
+}
+
+impl<K, V> Store<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub(crate) fn new(limits: Limits<V>, cancel_token: Option<CancellationToken>) -> Self {
+        let cancel_token = match cancel_token {
+            Some(cancel_token) => cancel_token.child_token(),
+            None => CancellationToken::new(),
+        };
+
+        Self {
+            storage: RwLock::new(Storage::new(limits)),
+            listeners: Mutex::new(Vec::new()),
+            has_listeners: AtomicBool::new(false),
+            stats: StatsCounter::default(),
+            expiry_changed: Arc::new(Notify::new()),
+            sweeper_started: AtomicBool::new(false),
+            cancel_token,
+        }
+    }
+
+    pub(crate) async fn len(&self) -> usize {
+        self.storage.read().await.entries.len()
+    }
+
+    pub(crate) async fn stats(&self) -> CacheStats {
+        let storage = self.storage.read().await;
+        self.stats.snapshot(storage.entries.len(), storage.bytes)
+    }
+
+    /// Returns a copy of the entry if it has not expired yet, counting a hit or a miss.
+    pub(crate) async fn get<Q>(&self, key: &Q) -> Option<Data<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let data = self.peek(key).await;
+        match data {
+            Some(_) => self.stats.hit(),
+            None => self.stats.miss(),
+        }
+        data
+    }
+
+    /// Same as [`Store::get`], without counting the lookup in the stats.
+    pub(crate) async fn peek<Q>(&self, key: &Q) -> Option<Data<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let storage = self.storage.read().await;
+        storage
+            .get(key)
+            .filter(|data| !data.is_expired(Instant::now()))
+            .cloned()
+    }
+
+    /// Returns false if the value is larger than the whole cache and was not stored.
+    pub(crate) async fn insert(self: &Arc<Self>, key: K, data: Data<V>) -> bool {
+        let expires_at = data.expires_at;
+
+        let mut storage = self.storage.write().await;
+        storage.track_removals = self.has_listeners.load(Ordering::Acquire);
+        let evicted = storage.insert(key, data);
+        let next_expiry = storage.next_expiry();
+        let removals = std::mem::take(&mut storage.removals);
+
+        drop(storage);
+        self.notify(removals);
+
+        if let Some(evicted) = evicted {
+            self.stats.insertion();
+            self.stats.evictions(evicted);
+        }
+
+        if expires_at.is_some() {
+            self.start_sweeper();
+            if next_expiry == expires_at {
+                self.expiry_changed.notify_one();
+            }
+        }
+
+        evicted.is_some()
+    }
+
+    pub(crate) async fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.write(|storage| storage.remove(key, RemovalCause::Removed))
+            .await
+            .is_some()
+    }
+
+    pub(crate) async fn remove_tag(&self, tag: &str) -> usize {
+        self.write(|storage| storage.remove_tag(tag)).await
+    }
+
+    pub(crate) async fn clear(&self) {
+        self.write(Storage::clear).await;
+    }
+
+    /// Registers `listener` to be called for every entry that leaves the cache from now on.
+    /// It is called after the write that removed the entry, outside of the storage lock, and
+    /// is dropped once it returns `false`.
+    pub(crate) fn add_listener(&self, listener: Listener<K>) {
+        self.listeners.lock().unwrap().push(listener);
+        self.has_listeners.store(true, Ordering::Release);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn listener_count(&self) -> usize {
+        self.listeners.lock().unwrap().len()
+    }
+
+    /// Runs `f` under the write lock, then notifies the listeners of what it removed.
+    async fn write<R>(&self, f: impl FnOnce(&mut Storage<K, V>) -> R) -> R {
+        let mut storage = self.storage.write().await;
+        storage.track_removals = self.has_listeners.load(Ordering::Acquire);
+        let result = f(&mut storage);
+        let removals = std::mem::take(&mut storage.removals);
+
+        drop(storage);
+        self.notify(removals);
+
+        result
+    }
+
+    fn notify(&self, removals: Vec<Removal<K>>) {
+        if removals.is_empty() {
+            return;
+        }
+
+        self.listeners
+            .lock()
+            .unwrap()
+            .retain(|listener| removals.iter().all(listener));
+    }
+
+    /// Copies of every entry that has not expired yet.
+    pub(crate) async fn entries(&self) -> Vec<(K, Data<V>)> {
+        let now = Instant::now();
+        self.storage
+            .read()
+            .await
+            .entries
+            .iter()
+            .filter(|(_, data)| !data.is_expired(now))
+            .map(|(key, data)| (key.clone(), data.clone()))
+            .collect()
+    }
+
+    fn start_sweeper(self: &Arc<Self>) {
+        if self.sweeper_started.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        tokio::spawn(Self::sweep(
+            Arc::downgrade(self),
+            self.expiry_changed.clone(),
+            self.cancel_token.clone(),
+        ));
+    }
+
+    /// Single background task that removes entries once their `expires_at` has passed.
+    /// It only keeps a weak reference so it never keeps a dropped cache alive.
+    async fn sweep(
+        store: Weak<Self>,
+        expiry_changed: Arc<Notify>,
+        cancel_token: CancellationToken,
+    ) {
+        loop {
+            let next_expiry = match store.upgrade() {
+                Some(store) => store.storage.read().await.next_expiry(),
+                None => return,
+            };
+
+            let deadline = async {
+                match next_expiry {
+                    Some(next_expiry) => sleep_until(next_expiry).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = cancel_token.cancelled() => return,
+                _ = expiry_changed.notified() => continue,
+                _ = deadline => {}
+            }
+
+            let Some(store) = store.upgrade() else {
+                return;
+            };
+            let removed = store
+                .write(|storage| storage.remove_expired(Instant::now()))
+                .await;
+            store.stats.expirations(removed);
+
+            #[cfg(feature = "log")]
+            log::debug!("[cache] expired {removed} entries");
+        }
+    }
+}
+
+impl<V> Store<String, V>
+where
+    V: Clone + Send + Sync + 'static,
+{
+    pub(crate) async fn remove_prefix(&self, prefix: &str) -> usize {
+        self.write(|storage| storage.remove_prefix(prefix)).await
+    }
+
+    pub(crate) async fn prefix_usage(&self, prefix: &str) -> (usize, usize) {
+        self.storage.read().await.prefix_usage(prefix)
+    }
+}
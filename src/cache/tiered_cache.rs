@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use super::CacheBackend;
+
+/// Two caches layered on top of each other, typically a process-local
+/// [`MemCache`](super::MemCache) in front of a shared or persistent backend.
+///
+/// Reads fall through L1 → L2, values found in L2 are copied into L1 for the time they have
+/// left to live, and writes go to both layers. The loader of [`CacheBackend::cached`] only
+/// runs when both layers miss.
+///
+/// Example
+/// ```rust,no_run
+/// use solar::cache::{CacheBackend, MemCache, PgCache, TieredCache};
+///
+/// #[tokio::main]
+/// async fn main() -> eyre::Result<()> {
+///     let pool = sqlx::PgPool::connect("postgres://localhost/solar").await?;
+///     let l2 = PgCache::new(pool);
+///     l2.migrate().await?;
+///
+///     let cache = TieredCache::new(MemCache::new(), l2);
+///     cache.set(&"sol_usd", 150.0, None).await;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TieredCache<L1, L2> {
+    l1: L1,
+    l2: L2,
+}
+
+impl<L1, L2> TieredCache<L1, L2> {
+    pub fn new(l1: L1, l2: L2) -> Self {
+        Self { l1, l2 }
+    }
+
+    pub fn l1(&self) -> &L1 {
+        &self.l1
+    }
+
+    pub fn l2(&self) -> &L2 {
+        &self.l2
+    }
+}
+
+#[async_trait::async_trait]
+impl<L1: CacheBackend, L2: CacheBackend> CacheBackend for TieredCache<L1, L2> {
+    async fn get_raw(&self, key: &str) -> Option<String> {
+        self.get_raw_with_ttl(key).await.map(|(value, _)| value)
+    }
+
+    async fn get_raw_with_ttl(&self, key: &str) -> Option<(String, Option<Duration>)> {
+        if let Some(hit) = self.l1.get_raw_with_ttl(key).await {
+            return Some(hit);
+        }
+
+        let (value, ttl) = self.l2.get_raw_with_ttl(key).await?;
+        self.l1.set_raw(key.to_string(), value.clone(), ttl).await;
+
+        #[cfg(feature = "log")]
+        log::debug!("[cache] l2 hit key={key}");
+
+        Some((value, ttl))
+    }
+
+    async fn set_raw(&self, key: String, value: String, expires_in: Option<Duration>) {
+        self.l2
+            .set_raw(key.clone(), value.clone(), expires_in)
+            .await;
+        self.l1.set_raw(key, value, expires_in).await;
+    }
+
+    async fn remove_raw(&self, key: &str) -> bool {
+        let removed_l2 = self.l2.remove_raw(key).await;
+        let removed_l1 = self.l1.remove_raw(key).await;
+
+        removed_l1 || removed_l2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemCache;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_read_falls_through_to_l2() {
+        let cache = TieredCache::new(MemCache::new(), MemCache::new());
+        cache.l2().set(&"a", 1, Some(Duration::from_secs(10))).await;
+
+        assert_eq!(cache.get::<_, i32>(&"a").await, Some(1));
+        assert_eq!(cache.l1().get::<_, i32>(&"a").await, Some(1));
+
+        // the L1 copy does not outlive the L2 entry
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert_eq!(cache.l1().get::<_, i32>(&"a").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_write_populates_both() {
+        let cache = TieredCache::new(MemCache::new(), MemCache::new());
+
+        cache.set(&"a", 1, None).await;
+
+        assert_eq!(cache.l1().get::<_, i32>(&"a").await, Some(1));
+        assert_eq!(cache.l2().get::<_, i32>(&"a").await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_remove_from_both() {
+        let cache = TieredCache::new(MemCache::new(), MemCache::new());
+
+        cache.set(&"a", 1, None).await;
+        assert!(cache.remove(&"a").await);
+
+        assert_eq!(cache.l1().get::<_, i32>(&"a").await, None);
+        assert_eq!(cache.l2().get::<_, i32>(&"a").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_cached_loads_on_miss_only() {
+        let cache = TieredCache::new(MemCache::new(), MemCache::new());
+        cache.l2().set(&"a", 1, None).await;
+
+        let value = cache
+            .cached(|| async { Err("not called".to_string()) }, "a", None)
+            .await;
+        assert_eq!(value, Ok(1));
+
+        let value = cache
+            .cached(|| async { Ok::<_, String>(2) }, "b", None)
+            .await;
+        assert_eq!(value, Ok(2));
+        assert_eq!(cache.l2().get::<_, i32>(&"b").await, Some(2));
+    }
+}
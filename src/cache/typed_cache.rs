@@ -0,0 +1,310 @@
+use std::borrow::Borrow;
+use std::future::Future;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::CacheStats;
+use super::storage::{Data, EvictionPolicy, Limits, Removal, Store};
+
+/// In-memory cache keyed by `K` that hands out `Arc<V>` without going through `serde_json`.
+///
+/// Entries expire, get evicted and are counted exactly like in [`MemCache`](super::MemCache),
+/// but a `TypedCache` only ever holds one value type, so a read can not ask for the wrong
+/// type and large values are shared instead of being parsed again on every hit. Values are
+/// not serialized, so the size of the cache is bounded by its number of entries only.
+///
+/// Example
+/// ```rust
+/// use std::time::Duration;
+///
+/// use solar::cache::TypedCache;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let cache: TypedCache<String, Vec<u8>> = TypedCache::builder().max_entries(1_000).build();
+///
+/// cache
+///     .set("account".to_string(), vec![0; 1024], Some(Duration::from_secs(5)))
+///     .await;
+/// let account = cache.get("account").await;
+/// # assert_eq!(account.unwrap().len(), 1024);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TypedCache<K, V> {
+    store: Arc<Store<K, Arc<V>>>,
+}
+
+impl<K, V> Clone for TypedCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+        }
+    }
+}
+
+/// Builder for a [`TypedCache`] with bounded capacity.
+#[derive(Debug, Clone)]
+pub struct TypedCacheBuilder<K, V> {
+    max_entries: Option<usize>,
+    eviction_policy: EvictionPolicy,
+    cancel_token: Option<CancellationToken>,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> Default for TypedCacheBuilder<K, V> {
+    fn default() -> Self {
+        Self {
+            max_entries: None,
+            eviction_policy: EvictionPolicy::default(),
+            cancel_token: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V> TypedCacheBuilder<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum number of entries kept in the cache.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    /// Stops the expiry sweeper once the token is cancelled. The sweeper also
+    /// stops on its own when the last handle to the cache is dropped.
+    pub fn cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.cancel_token = Some(cancel_token);
+        self
+    }
+
+    pub fn build(self) -> TypedCache<K, V> {
+        let limits = Limits {
+            max_entries: self.max_entries,
+            max_bytes: None,
+            policy: self.eviction_policy,
+            weigher: |_| 0,
+        };
+
+        TypedCache {
+            store: Arc::new(Store::new(limits, self.cancel_token)),
+        }
+    }
+}
+
+impl<K, V> Default for TypedCache<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> TypedCache<K, V>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        TypedCacheBuilder::new().build()
+    }
+
+    pub fn builder() -> TypedCacheBuilder<K, V> {
+        TypedCacheBuilder::new()
+    }
+
+    pub async fn len(&self) -> usize {
+        self.store.len().await
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Same counters as [`MemCache::stats`](super::MemCache::stats), `bytes` is always zero.
+    pub async fn stats(&self) -> CacheStats {
+        self.store.stats().await
+    }
+
+    pub async fn get<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.store.get(key).await.map(|data| data.value)
+    }
+
+    pub async fn set(
+        &self,
+        key: K,
+        value: impl Into<Arc<V>>,
+        expires_in: Option<Duration>,
+    ) -> Arc<V> {
+        let value = value.into();
+        self.store
+            .insert(key, Data::new(value.clone(), expires_in))
+            .await;
+
+        value
+    }
+
+    /// Same as [`TypedCache::set`], additionally attaching `tags` to the entry so that it
+    /// can be dropped together with other entries through [`TypedCache::invalidate_tag`].
+    pub async fn set_with_tags(
+        &self,
+        key: K,
+        value: impl Into<Arc<V>>,
+        expires_in: Option<Duration>,
+        tags: &[&str],
+    ) -> Arc<V> {
+        let value = value.into();
+        let tags = tags.iter().map(|tag| tag.to_string()).collect();
+        self.store
+            .insert(key, Data::new(value.clone(), expires_in).with_tags(tags))
+            .await;
+
+        value
+    }
+
+    /// Removes the entry stored under `key`, returns whether there was one.
+    pub async fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.store.remove(key).await
+    }
+
+    /// Removes every entry stored with `tag` and returns how many were removed.
+    pub async fn invalidate_tag(&self, tag: &str) -> usize {
+        self.store.remove_tag(tag).await
+    }
+
+    pub async fn clear(&self) {
+        self.store.clear().await;
+    }
+
+    /// Same as [`MemCache::on_removal`](super::MemCache::on_removal), with typed keys.
+    pub fn on_removal(&self, listener: impl Fn(&Removal<K>) + Send + Sync + 'static) {
+        self.store.add_listener(Box::new(move |removal| {
+            listener(removal);
+            true
+        }));
+    }
+
+    /// Same as [`MemCache::subscribe_removals`](super::MemCache::subscribe_removals), with
+    /// typed keys.
+    pub fn subscribe_removals(&self) -> mpsc::UnboundedReceiver<Removal<K>> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.store.add_listener(Box::new(move |removal| {
+            sender.send(removal.clone()).is_ok()
+        }));
+
+        receiver
+    }
+
+    pub async fn cached<F, Fut, E>(
+        &self,
+        action_fn: F,
+        key: K,
+        expires_in: Option<Duration>,
+    ) -> Result<Arc<V>, E>
+    where
+        K: std::fmt::Debug,
+        Fut: Future<Output = Result<V, E>>,
+        F: FnOnce() -> Fut,
+    {
+        if let Some(value) = self.get(&key).await {
+            #[cfg(feature = "log")]
+            log::debug!("[cache] cache hit key={key:?}");
+
+            return Ok(value);
+        }
+
+        match action_fn().await {
+            Err(err) => {
+                self.store.stats.loader_error();
+                Err(err)
+            }
+            Ok(value) => {
+                #[cfg(feature = "log")]
+                log::debug!("[cache] new entry key={key:?}");
+
+                Ok(self.set(key, value, expires_in).await)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_get_set_shares_value() {
+        let cache = TypedCache::<String, Vec<u8>>::new();
+
+        let value = cache.set("a".to_string(), vec![1, 2, 3], None).await;
+        let cached = cache.get("a").await.unwrap();
+
+        assert!(Arc::ptr_eq(&value, &cached));
+        assert_eq!(cache.get("missing").await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expiry_and_eviction() {
+        let cache = TypedCache::<u64, u64>::builder().max_entries(2).build();
+
+        cache.set(1, 1, Some(Duration::from_secs(1))).await;
+        cache.set(2, 2, None).await;
+        cache.set(3, 3, None).await;
+        assert_eq!(cache.get(&1).await, None);
+
+        cache.set(4, 4, Some(Duration::from_secs(1))).await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        assert_eq!(cache.get(&4).await, None);
+        assert_eq!(cache.len().await, 1);
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.evictions, 2);
+        assert_eq!(stats.expirations, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cached_and_invalidation() {
+        let cache = TypedCache::<&'static str, i32>::new();
+
+        let value = cache
+            .cached(|| async { Ok::<_, String>(1) }, "a", None)
+            .await;
+        assert_eq!(value.as_deref(), Ok(&1));
+        let value = cache
+            .cached(|| async { Err("not called".to_string()) }, "a", None)
+            .await;
+        assert_eq!(value.as_deref(), Ok(&1));
+
+        cache.set_with_tags("b", 2, None, &["wallet"]).await;
+        assert_eq!(cache.invalidate_tag("wallet").await, 1);
+        assert!(cache.remove(&"a").await);
+        assert!(cache.is_empty().await);
+    }
+}
@@ -0,0 +1,242 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use super::RateLimitedClient;
+use super::priority::Slots;
+
+/// Bounds of a concurrency limit discovered at runtime, see
+/// [`super::RateLimitedClientBuilder::adaptive_concurrency`].
+///
+/// The limit grows by one after a full limit's worth of successful calls and is multiplied
+/// by `backoff` when a call is rate limited (AIMD), staying between `min` and `max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveConcurrency {
+    pub initial: usize,
+    pub min: usize,
+    pub max: usize,
+    pub backoff: f64,
+}
+
+impl AdaptiveConcurrency {
+    /// Starts at `initial` calls at once and halves the limit on every rate limited call.
+    pub fn new(initial: usize, min: usize, max: usize) -> Self {
+        let min = min.max(1);
+        let max = max.max(min);
+
+        Self {
+            initial: initial.clamp(min, max),
+            min,
+            max,
+            backoff: 0.5,
+        }
+    }
+
+    /// Factor applied to the limit when a call is rate limited, between 0 and 1.
+    pub fn with_backoff(mut self, backoff: f64) -> Self {
+        self.backoff = backoff.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Same bounds, never going above `ceiling`.
+    pub(super) fn capped(self, ceiling: usize) -> Self {
+        let ceiling = ceiling.max(1);
+        Self::new(self.initial, self.min.min(ceiling), self.max.min(ceiling))
+            .with_backoff(self.backoff)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    // bumped on every decrease, so that the calls already in flight at that point do not
+    // lower the limit again for the same overload
+    epoch: u64,
+}
+
+#[derive(Debug)]
+pub(super) struct Aimd {
+    config: AdaptiveConcurrency,
+    state: Mutex<State>,
+}
+
+impl Aimd {
+    pub(super) fn new(config: AdaptiveConcurrency) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State {
+                limit: config.initial as f64,
+                epoch: 0,
+            }),
+        }
+    }
+
+    fn epoch(&self) -> u64 {
+        self.state.lock().unwrap().epoch
+    }
+
+    fn record(&self, slots: &Slots, rate_limited: bool, epoch: u64) {
+        let mut state = self.state.lock().unwrap();
+
+        if rate_limited {
+            if epoch != state.epoch {
+                return;
+            }
+            state.limit = (state.limit * self.config.backoff).max(self.config.min as f64);
+            state.epoch += 1;
+            #[cfg(feature = "log")]
+            log::info!(
+                client = "RateLimitedClient";
+                "concurrency limit lowered to {}",
+                state.limit as usize
+            );
+        } else {
+            state.limit = (state.limit + 1.0 / state.limit).min(self.config.max as f64);
+        }
+
+        slots.set_max(state.limit as usize);
+    }
+}
+
+impl<T> RateLimitedClient<T> {
+    /// Same as [`RateLimitedClient::call`], adjusting the adaptive concurrency limit of the
+    /// client to the outcome: down if `is_rate_limited` returns true for the error, e.g. on
+    /// a 429, up otherwise.
+    ///
+    /// Without adaptive concurrency on the builder this is a plain call.
+    pub async fn call_adaptive<F, Fut, R, E>(
+        &self,
+        func: F,
+        is_rate_limited: impl FnOnce(&E) -> bool,
+    ) -> Result<R, E>
+    where
+        F: FnOnce(Arc<T>) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+    {
+        let (Some(aimd), Some(slots)) = (&self.aimd, &self.slots) else {
+            return self.call(func).await;
+        };
+
+        let mut epoch = None;
+        let result = self
+            .call(|client| {
+                // taken once the call has a slot, after any decrease it waited for
+                epoch = Some(aimd.epoch());
+                func(client)
+            })
+            .await;
+
+        let rate_limited = result.as_ref().err().is_some_and(is_rate_limited);
+        aimd.record(slots, rate_limited, epoch.unwrap_or_default());
+
+        result
+    }
+
+    /// Concurrency limit currently in use: the fixed `max_concurrent`, or the one discovered
+    /// so far with [`super::RateLimitedClientBuilder::adaptive_concurrency`].
+    pub fn concurrency_limit(&self) -> Option<usize> {
+        self.slots.as_ref().map(|slots| slots.max())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tokio::task::JoinSet;
+    use tokio::time::sleep;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct TooManyRequests;
+
+    /// Upstream accepting up to `capacity` calls at once, rejecting the others.
+    struct Upstream {
+        capacity: usize,
+        in_flight: AtomicUsize,
+    }
+
+    impl Upstream {
+        async fn call(&self) -> Result<(), TooManyRequests> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            sleep(Duration::from_millis(100)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            match in_flight <= self.capacity {
+                true => Ok(()),
+                false => Err(TooManyRequests),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_discovers_limit() {
+        let upstream = Upstream {
+            capacity: 5,
+            in_flight: AtomicUsize::new(0),
+        };
+        let client = RateLimitedClient::builder(upstream)
+            .adaptive_concurrency(AdaptiveConcurrency::new(20, 1, 50))
+            .build();
+        assert_eq!(client.concurrency_limit(), Some(20));
+
+        let mut calls = JoinSet::new();
+        for _ in 0..500 {
+            let client = client.clone();
+            calls.spawn(async move {
+                client
+                    .call_adaptive(|upstream| async move { upstream.call().await }, |_| true)
+                    .await
+            });
+        }
+        let results = calls.join_all().await;
+
+        let limit = client.concurrency_limit().unwrap();
+        assert!((3..=7).contains(&limit), "limit {limit}");
+        let rejected = results.iter().filter(|result| result.is_err()).count();
+        assert!(rejected < 100, "{rejected} calls rejected");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_concurrent_is_the_ceiling() {
+        let client = RateLimitedClient::builder(())
+            .max_concurrent(3)
+            .adaptive_concurrency(AdaptiveConcurrency::new(2, 1, 10))
+            .build();
+        assert_eq!(client.concurrency_limit(), Some(2));
+
+        for _ in 0..20 {
+            let _ = client
+                .call_adaptive(|_| async { Ok::<_, TooManyRequests>(()) }, |_| true)
+                .await;
+        }
+        assert_eq!(client.concurrency_limit(), Some(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ramps_up_slowly() {
+        let client = RateLimitedClient::builder(())
+            .adaptive_concurrency(AdaptiveConcurrency::new(2, 1, 4))
+            .build();
+
+        for _ in 0..3 {
+            let _ = client
+                .call_adaptive(|_| async { Ok::<_, TooManyRequests>(()) }, |_| true)
+                .await;
+        }
+        assert_eq!(client.concurrency_limit(), Some(3));
+
+        for _ in 0..20 {
+            let _ = client
+                .call_adaptive(|_| async { Ok::<_, TooManyRequests>(()) }, |_| true)
+                .await;
+        }
+        assert_eq!(client.concurrency_limit(), Some(4));
+
+        let _ = client
+            .call_adaptive(|_| async { Err::<(), _>(TooManyRequests) }, |_| true)
+            .await;
+        assert_eq!(client.concurrency_limit(), Some(2));
+    }
+}
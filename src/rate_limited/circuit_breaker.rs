@@ -0,0 +1,522 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// State of a [`CircuitBreaker`], e.g. for a health check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through, failures are counted.
+    Closed,
+    /// Calls fail fast until the cool-down is over.
+    Open,
+    /// A limited number of trial calls go through to decide whether to close again.
+    HalfOpen,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum CircuitError<E> {
+    #[error("circuit open, retry in {retry_in:?}")]
+    Open { retry_in: Duration },
+    /// The circuit is half-open and its trial calls are all in progress, the next call may
+    /// go through once they finish.
+    #[error("circuit half-open, trial calls in progress")]
+    HalfOpen,
+    #[error(transparent)]
+    Inner(E),
+}
+
+impl<E> CircuitError<E> {
+    pub fn into_inner(self) -> Option<E> {
+        match self {
+            Self::Open { .. } | Self::HalfOpen => None,
+            Self::Inner(err) => Some(err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Thresholds {
+    failure_threshold: u32,
+    cool_down: Duration,
+    half_open_calls: u32,
+    success_threshold: u32,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        // identifies the half-open window, so that a trial call finishing in a later one
+        // does not count there
+        epoch: u64,
+        in_flight: u32,
+        successes: u32,
+    },
+}
+
+#[derive(Debug)]
+struct Breaker {
+    thresholds: Thresholds,
+    state: Mutex<State>,
+    epochs: AtomicU64,
+}
+
+impl Breaker {
+    /// Returns the epoch of the half-open window if the call is one of its trial calls.
+    fn try_acquire<E>(&self) -> Result<Option<u64>, CircuitError<E>> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        match &mut *state {
+            State::Closed { .. } => Ok(None),
+            State::Open { until } if *until > now => Err(CircuitError::Open {
+                retry_in: *until - now,
+            }),
+            State::Open { .. } => {
+                let epoch = self.epochs.fetch_add(1, Ordering::SeqCst);
+                *state = State::HalfOpen {
+                    epoch,
+                    in_flight: 1,
+                    successes: 0,
+                };
+                Ok(Some(epoch))
+            }
+            State::HalfOpen {
+                epoch, in_flight, ..
+            } => {
+                if *in_flight >= self.thresholds.half_open_calls {
+                    return Err(CircuitError::HalfOpen);
+                }
+                *in_flight += 1;
+                Ok(Some(*epoch))
+            }
+        }
+    }
+
+    fn record(&self, success: bool, trial: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+
+        match &mut *state {
+            State::Closed { failures } if success => *failures = 0,
+            State::Closed { failures } => {
+                *failures += 1;
+                if *failures >= self.thresholds.failure_threshold {
+                    self.open(&mut state);
+                }
+            }
+            // the outcome of a call started before the circuit opened says nothing new
+            State::Open { .. } => {}
+            State::HalfOpen { epoch, .. } if trial != Some(*epoch) => {}
+            State::HalfOpen { .. } if !success => self.open(&mut state),
+            State::HalfOpen {
+                in_flight,
+                successes,
+                ..
+            } => {
+                *in_flight -= 1;
+                *successes += 1;
+                if *successes >= self.thresholds.success_threshold {
+                    #[cfg(feature = "log")]
+                    log::info!(client = "CircuitBreaker"; "circuit closed");
+                    *state = State::Closed { failures: 0 };
+                }
+            }
+        }
+    }
+
+    /// Gives back the slot of a trial call of `epoch` dropped before it finished.
+    fn release(&self, trial: u64) {
+        if let State::HalfOpen {
+            epoch, in_flight, ..
+        } = &mut *self.state.lock().unwrap()
+            && *epoch == trial
+        {
+            *in_flight = in_flight.saturating_sub(1);
+        }
+    }
+
+    fn open(&self, state: &mut State) {
+        #[cfg(feature = "log")]
+        log::warn!(client = "CircuitBreaker"; "circuit open for {:?}", self.thresholds.cool_down);
+        *state = State::Open {
+            until: Instant::now() + self.thresholds.cool_down,
+        };
+    }
+
+    fn state(&self) -> CircuitState {
+        match &*self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { until } if *until > Instant::now() => CircuitState::Open,
+            State::Open { .. } | State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+/// Records the outcome of a call when it finishes, or releases its slot if dropped early.
+struct Attempt<'a> {
+    breaker: &'a Breaker,
+    trial: Option<u64>,
+    done: bool,
+}
+
+impl Attempt<'_> {
+    fn finish(mut self, success: bool) {
+        self.done = true;
+        self.breaker.record(success, self.trial);
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if let Some(epoch) = self.trial
+            && !self.done
+        {
+            self.breaker.release(epoch);
+        }
+    }
+}
+
+/// Client wrapper failing fast while its endpoint is down, instead of having every call
+/// wait for a timeout.
+///
+/// After `failure_threshold` failed calls in a row the circuit opens and calls return
+/// [`CircuitError::Open`] without reaching the client. Once `cool_down` has passed, up to
+/// `half_open_calls` trial calls are let through, the calls beyond them returning
+/// [`CircuitError::HalfOpen`]: `success_threshold` successes close the circuit, any failure
+/// opens it again.
+///
+/// It wraps a [`super::RateLimitedClient`] like any other client, e.g. to stop spending
+/// quota on an endpoint that is down.
+///
+/// Example
+/// ```rust
+/// use std::time::Duration;
+///
+/// use solar::rate_limited::{CircuitBreaker, CircuitError, CircuitState};
+///
+/// struct InternalClient;
+///
+/// impl InternalClient {
+///     async fn get_slot(&self) -> Result<u64, String> {
+///         Err("connection refused".to_string())
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client = CircuitBreaker::builder(InternalClient)
+///         .failure_threshold(1)
+///         .cool_down(Duration::from_secs(30))
+///         .build();
+///
+///     let result = client.call(|client| async move { client.get_slot().await }).await;
+///     assert!(matches!(result, Err(CircuitError::Inner(_))));
+///
+///     let result = client.call(|client| async move { client.get_slot().await }).await;
+///     assert!(matches!(result, Err(CircuitError::Open { .. })));
+///     assert_eq!(client.state(), CircuitState::Open);
+/// }
+/// ```
+pub struct CircuitBreaker<T> {
+    client: Arc<T>,
+    breaker: Arc<Breaker>,
+}
+
+impl<T> Clone for CircuitBreaker<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: Arc::clone(&self.client),
+            breaker: Arc::clone(&self.breaker),
+        }
+    }
+}
+
+/// Builder for a [`CircuitBreaker`], opening after 5 failures for 30s by default.
+pub struct CircuitBreakerBuilder<T> {
+    client: T,
+    thresholds: Thresholds,
+}
+
+impl<T> CircuitBreakerBuilder<T> {
+    pub fn new(client: T) -> Self {
+        Self {
+            client,
+            thresholds: Thresholds {
+                failure_threshold: 5,
+                cool_down: Duration::from_secs(30),
+                half_open_calls: 1,
+                success_threshold: 1,
+            },
+        }
+    }
+
+    /// Failed calls in a row that open the circuit.
+    pub fn failure_threshold(mut self, failure_threshold: u32) -> Self {
+        self.thresholds.failure_threshold = failure_threshold.max(1);
+        self
+    }
+
+    /// How long the circuit stays open before trial calls are let through.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.thresholds.cool_down = cool_down;
+        self
+    }
+
+    /// Maximum number of trial calls in progress while half-open.
+    pub fn half_open_calls(mut self, half_open_calls: u32) -> Self {
+        self.thresholds.half_open_calls = half_open_calls.max(1);
+        self
+    }
+
+    /// Successful trial calls needed to close the circuit again.
+    pub fn success_threshold(mut self, success_threshold: u32) -> Self {
+        self.thresholds.success_threshold = success_threshold.max(1);
+        self
+    }
+
+    pub fn build(self) -> CircuitBreaker<T> {
+        CircuitBreaker {
+            client: Arc::new(self.client),
+            breaker: Arc::new(Breaker {
+                thresholds: self.thresholds,
+                state: Mutex::new(State::Closed { failures: 0 }),
+                epochs: AtomicU64::new(0),
+            }),
+        }
+    }
+}
+
+impl<T> CircuitBreaker<T> {
+    pub fn builder(client: T) -> CircuitBreakerBuilder<T> {
+        CircuitBreakerBuilder::new(client)
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.breaker.state()
+    }
+
+    /// The wrapped client, for calls that should not go through the circuit.
+    pub fn inner(&self) -> &T {
+        &self.client
+    }
+
+    /// Makes the call unless the circuit is open, counting any `Err` as a failure.
+    pub async fn call<F, Fut, R, E>(&self, func: F) -> Result<R, CircuitError<E>>
+    where
+        F: FnOnce(Arc<T>) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+    {
+        self.call_classified(func, |_| true).await
+    }
+
+    /// Same as [`CircuitBreaker::call`], counting only the errors for which `is_failure`
+    /// returns true, so that e.g. an invalid request does not open the circuit.
+    pub async fn call_classified<F, Fut, R, E>(
+        &self,
+        func: F,
+        is_failure: impl FnOnce(&E) -> bool,
+    ) -> Result<R, CircuitError<E>>
+    where
+        F: FnOnce(Arc<T>) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+    {
+        let trial = self.breaker.try_acquire()?;
+        let attempt = Attempt {
+            breaker: &self.breaker,
+            trial,
+            done: false,
+        };
+
+        let result = func(Arc::clone(&self.client)).await;
+        attempt.finish(
+            result
+                .as_ref()
+                .map_or_else(|err| !is_failure(err), |_| true),
+        );
+
+        result.map_err(CircuitError::Inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use tokio::time::sleep;
+
+    use super::*;
+
+    fn breaker() -> CircuitBreaker<AtomicBool> {
+        CircuitBreaker::builder(AtomicBool::new(false))
+            .failure_threshold(3)
+            .cool_down(Duration::from_secs(10))
+            .build()
+    }
+
+    async fn call(client: &CircuitBreaker<AtomicBool>) -> Result<(), CircuitError<&'static str>> {
+        client
+            .call(|up| async move {
+                match up.load(Ordering::SeqCst) {
+                    true => Ok(()),
+                    false => Err("down"),
+                }
+            })
+            .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_opens_and_closes() {
+        let client = breaker();
+
+        for _ in 0..3 {
+            assert_eq!(call(&client).await, Err(CircuitError::Inner("down")));
+        }
+        assert_eq!(client.state(), CircuitState::Open);
+        assert_eq!(
+            call(&client).await,
+            Err(CircuitError::Open {
+                retry_in: Duration::from_secs(10)
+            })
+        );
+
+        // a failed trial call opens the circuit for another cool-down
+        sleep(Duration::from_secs(10)).await;
+        assert_eq!(client.state(), CircuitState::HalfOpen);
+        assert_eq!(call(&client).await, Err(CircuitError::Inner("down")));
+        assert_eq!(client.state(), CircuitState::Open);
+
+        sleep(Duration::from_secs(10)).await;
+        client.inner().store(true, Ordering::SeqCst);
+        assert_eq!(call(&client).await, Ok(()));
+        assert_eq!(client.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_successes_reset_failures() {
+        let client = breaker();
+
+        for _ in 0..5 {
+            client.inner().store(false, Ordering::SeqCst);
+            assert!(call(&client).await.is_err());
+            assert!(call(&client).await.is_err());
+            client.inner().store(true, Ordering::SeqCst);
+            assert!(call(&client).await.is_ok());
+        }
+
+        assert_eq!(client.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_half_open_limits_trial_calls() {
+        let client = breaker();
+        for _ in 0..3 {
+            let _ = call(&client).await;
+        }
+        sleep(Duration::from_secs(10)).await;
+        client.inner().store(true, Ordering::SeqCst);
+
+        let slow = client.clone();
+        let trial = tokio::spawn(async move {
+            slow.call(|_| async {
+                sleep(Duration::from_secs(1)).await;
+                Ok::<_, &str>(())
+            })
+            .await
+        });
+        tokio::task::yield_now().await;
+
+        assert_eq!(call(&client).await, Err(CircuitError::HalfOpen));
+        assert_eq!(trial.await.unwrap(), Ok(()));
+        assert_eq!(call(&client).await, Ok(()));
+
+        // a trial call dropped before finishing gives its slot back
+        let client = breaker();
+        for _ in 0..3 {
+            let _ = call(&client).await;
+        }
+        sleep(Duration::from_secs(10)).await;
+        let dropped = client.call(|_| async {
+            sleep(Duration::from_secs(1)).await;
+            Ok::<_, &str>(())
+        });
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), dropped)
+                .await
+                .is_err()
+        );
+        client.inner().store(true, Ordering::SeqCst);
+        assert_eq!(call(&client).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_call_classified() {
+        let client = breaker();
+
+        for _ in 0..5 {
+            let result = client
+                .call_classified(
+                    |_| async { Err::<(), _>("invalid params") },
+                    |err| *err != "invalid params",
+                )
+                .await;
+            assert_eq!(result, Err(CircuitError::Inner("invalid params")));
+        }
+
+        assert_eq!(client.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_trial_calls_are_ignored() {
+        let client = CircuitBreaker::builder(AtomicBool::new(true))
+            .failure_threshold(1)
+            .cool_down(Duration::from_secs(10))
+            .half_open_calls(2)
+            .build();
+        let _ = client.call(|_| async { Err::<(), _>("down") }).await;
+        sleep(Duration::from_secs(10)).await;
+
+        // trial of the first half-open window, still running after the next one begins
+        let slow = client.clone();
+        let stale = tokio::spawn(async move {
+            slow.call(|_| async {
+                sleep(Duration::from_secs(30)).await;
+                Ok::<_, &str>(())
+            })
+            .await
+        });
+        tokio::task::yield_now().await;
+        let _ = client.call(|_| async { Err::<(), _>("down") }).await;
+        sleep(Duration::from_secs(10)).await;
+
+        let mut trials = Vec::new();
+        for _ in 0..2 {
+            let slow = client.clone();
+            trials.push(tokio::spawn(async move {
+                slow.call(|_| async {
+                    sleep(Duration::from_secs(30)).await;
+                    Err::<(), _>("down")
+                })
+                .await
+            }));
+        }
+        tokio::task::yield_now().await;
+
+        // the stale success neither frees a trial slot nor closes the circuit
+        assert_eq!(stale.await.unwrap(), Ok(()));
+        assert_eq!(client.state(), CircuitState::HalfOpen);
+        assert_eq!(call(&client).await, Err(CircuitError::HalfOpen));
+
+        for trial in trials {
+            assert_eq!(trial.await.unwrap(), Err(CircuitError::Inner("down")));
+        }
+        assert_eq!(client.state(), CircuitState::Open);
+    }
+}
@@ -0,0 +1,450 @@
+use std::future::Future;
+use std::sync::Arc;
+
+#[cfg(feature = "trx_factory")]
+use super::PgRateLimiter;
+use super::adaptive::Aimd;
+use super::priority::{Reserves, Slot, Slots};
+use super::{AdaptiveConcurrency, Priority, Quota, RateLimiter};
+
+/// Where the quota of a client is kept.
+#[derive(Debug, Clone)]
+enum Limiter {
+    Local(Arc<RateLimiter>),
+    #[cfg(feature = "trx_factory")]
+    Shared(Arc<PgRateLimiter>),
+}
+
+/// Example
+/// ```rust
+/// use solar::rate_limited::{Quota, RateLimitedClient};
+///
+/// struct InternalClient;
+///
+/// impl InternalClient {
+///     async fn some_fn(&self) {
+///         println!("Executing request...");
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client = InternalClient;
+///     let rate_limited_client = RateLimitedClient::builder(client)
+///         .quota(Quota::per_second(10).with_burst(3))
+///         .max_concurrent(2)
+///         .build();
+///
+///     rate_limited_client
+///         .call(|client| async move { client.some_fn().await })
+///         .await;
+///
+///     // Calling `execute` again requires a new slot
+///     rate_limited_client
+///         .call(|client| async move { client.some_fn().await })
+///         .await;
+/// }
+/// ```
+pub struct RateLimitedClient<T> {
+    pub(super) client: Arc<T>,
+    limiter: Option<Limiter>,
+    pub(super) slots: Option<Arc<Slots>>,
+    pub(super) aimd: Option<Arc<Aimd>>,
+    reserves: Reserves,
+    priority: Priority,
+    disable_limit: bool,
+}
+
+impl<T> Clone for RateLimitedClient<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: Arc::clone(&self.client),
+            limiter: self.limiter.clone(),
+            slots: self.slots.clone(),
+            aimd: self.aimd.clone(),
+            reserves: self.reserves,
+            priority: self.priority,
+            disable_limit: self.disable_limit,
+        }
+    }
+}
+
+/// Builder for a [`RateLimitedClient`]. Without a quota or a concurrency limit calls are
+/// not limited at all.
+///
+/// Timeouts, cancellation and hedging are set on a [`super::GuardedClient`] wrapping the
+/// built client, whose calls can fail with a [`super::CallError`].
+pub struct RateLimitedClientBuilder<T> {
+    client: T,
+    limiter: Option<Limiter>,
+    max_concurrent: Option<usize>,
+    adaptive_concurrency: Option<AdaptiveConcurrency>,
+    reserves: Reserves,
+}
+
+impl<T> RateLimitedClientBuilder<T> {
+    pub fn new(client: T) -> Self {
+        Self {
+            client,
+            limiter: None,
+            max_concurrent: None,
+            adaptive_concurrency: None,
+            reserves: Reserves::default(),
+        }
+    }
+
+    /// Requests per period the client is allowed to make.
+    pub fn quota(mut self, quota: Quota) -> Self {
+        self.limiter = Some(Limiter::Local(Arc::new(RateLimiter::new(quota))));
+        self
+    }
+
+    /// Quota shared with the other processes using the same [`PgRateLimiter`] key, instead
+    /// of a quota of this client only. Priority reserves do not apply to it.
+    #[cfg(feature = "trx_factory")]
+    pub fn shared_limiter(mut self, limiter: Arc<PgRateLimiter>) -> Self {
+        self.limiter = Some(Limiter::Shared(limiter));
+        self
+    }
+
+    /// Maximum number of calls in progress at the same time, independent of the quota. With
+    /// [`RateLimitedClientBuilder::adaptive_concurrency`] it is the ceiling of the adaptive
+    /// limit.
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = Some(max_concurrent);
+        self
+    }
+
+    /// Concurrency limit adjusted to the rate limit errors of the calls made with
+    /// [`RateLimitedClient::call_adaptive`], instead of a fixed `max_concurrent`, which then
+    /// only caps its `max`.
+    pub fn adaptive_concurrency(mut self, adaptive_concurrency: AdaptiveConcurrency) -> Self {
+        self.adaptive_concurrency = Some(adaptive_concurrency);
+        self
+    }
+
+    /// Sets aside `slots` of both the quota burst and the concurrency limit for calls of
+    /// `priority` and above, so that they get through while lower priorities saturate the
+    /// client.
+    pub fn reserve(mut self, priority: Priority, slots: u32) -> Self {
+        self.reserves.set(priority, slots);
+        self
+    }
+
+    pub fn build(self) -> RateLimitedClient<T> {
+        let adaptive_concurrency =
+            self.adaptive_concurrency
+                .map(|adaptive| match self.max_concurrent {
+                    Some(max_concurrent) => adaptive.capped(max_concurrent),
+                    None => adaptive,
+                });
+
+        RateLimitedClient {
+            client: Arc::new(self.client),
+            limiter: self.limiter,
+            slots: adaptive_concurrency
+                .map(|adaptive| adaptive.initial)
+                .or(self.max_concurrent)
+                .map(|max_concurrent| Arc::new(Slots::new(max_concurrent))),
+            aimd: adaptive_concurrency.map(|adaptive| Arc::new(Aimd::new(adaptive))),
+            reserves: self.reserves,
+            priority: Priority::default(),
+            disable_limit: false,
+        }
+    }
+}
+
+impl<T> RateLimitedClient<T> {
+    /// Client allowed `max_concurrent` calls at once and as many per second, close to the
+    /// permits this constructor used to hold for a second after each call.
+    ///
+    /// Panics if `max_concurrent` is zero.
+    #[deprecated(note = "use `RateLimitedClient::per_second` or `RateLimitedClient::builder`")]
+    pub fn new(client: T, max_concurrent: usize) -> Self {
+        assert!(
+            max_concurrent > 0,
+            "RateLimitedClient needs a positive max_concurrent"
+        );
+
+        Self::builder(client)
+            .quota(Quota::per_second(
+                u32::try_from(max_concurrent).unwrap_or(u32::MAX),
+            ))
+            .max_concurrent(max_concurrent)
+            .build()
+    }
+
+    /// Client allowed `requests_per_second` calls per second, all of which may be sent at
+    /// once.
+    ///
+    /// Panics if `requests_per_second` is zero.
+    pub fn per_second(client: T, requests_per_second: u32) -> Self {
+        assert!(
+            requests_per_second > 0,
+            "RateLimitedClient needs a positive requests_per_second"
+        );
+
+        Self::builder(client)
+            .quota(Quota::per_second(requests_per_second))
+            .build()
+    }
+
+    pub fn builder(client: T) -> RateLimitedClientBuilder<T> {
+        RateLimitedClientBuilder::new(client)
+    }
+
+    /// The wrapped client, for calls that should not go through the limits.
+    pub fn inner(&self) -> &T {
+        &self.client
+    }
+
+    /// Handle sharing the limits of this client whose calls are made with `priority`.
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        Self {
+            priority,
+            ..self.clone()
+        }
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    pub fn disable_limit(&mut self) {
+        self.disable_limit = true;
+    }
+
+    /// Makes the call once it fits in the limits, waiting as long as it takes. See
+    /// [`super::GuardedClient`] to bound the wait.
+    pub async fn call<F, Fut, R>(&self, func: F) -> R
+    where
+        F: FnOnce(Arc<T>) -> Fut,
+        Fut: Future<Output = R>,
+    {
+        self.call_weighted(1, func).await
+    }
+
+    /// Same as [`RateLimitedClient::call`], using up `cost` units of the quota, e.g. the
+    /// credits an RPC provider charges for the method.
+    pub async fn call_weighted<F, Fut, R>(&self, cost: u32, func: F) -> R
+    where
+        F: FnOnce(Arc<T>) -> Fut,
+        Fut: Future<Output = R>,
+    {
+        let _slot = self.acquire(cost).await;
+
+        let client = Arc::clone(&self.client);
+        func(client).await
+    }
+
+    /// Waits for a concurrency slot and `cost` units of the quota.
+    pub(super) async fn acquire(&self, cost: u32) -> Option<Slot> {
+        if self.disable_limit {
+            return None;
+        }
+
+        let reserved = self.reserves.above(self.priority);
+
+        // the concurrency slot is taken first, so that waiting for it does not use up quota
+        let slot = match &self.slots {
+            Some(slots) => Some(slots.acquire(reserved as usize).await),
+            None => None,
+        };
+        match &self.limiter {
+            Some(Limiter::Local(limiter)) => limiter.acquire_reserved(cost, reserved).await,
+            #[cfg(feature = "trx_factory")]
+            Some(Limiter::Shared(limiter)) => limiter.acquire_n(cost).await,
+            None => {}
+        }
+
+        slot
+    }
+
+    /// Takes a concurrency slot and `cost` units of the quota only if both are free right
+    /// now, a shared limiter only giving out the tokens this process already holds.
+    pub(super) fn try_acquire(&self, cost: u32) -> Option<Option<Slot>> {
+        if self.disable_limit {
+            return Some(None);
+        }
+
+        let reserved = self.reserves.above(self.priority);
+        let slot = match &self.slots {
+            Some(slots) => Some(slots.try_acquire(reserved as usize)?),
+            None => None,
+        };
+        let acquired = match &self.limiter {
+            Some(Limiter::Local(limiter)) => limiter.try_acquire_reserved(cost, reserved).is_ok(),
+            #[cfg(feature = "trx_factory")]
+            Some(Limiter::Shared(limiter)) => limiter.take_local(cost),
+            None => true,
+        };
+
+        acquired.then_some(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tokio::task::JoinSet;
+    use tokio::time::{Instant, sleep};
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_does_not_depend_on_latency() {
+        let client = RateLimitedClient::builder(())
+            .quota(Quota::per_second(5).with_burst(1))
+            .build();
+        let start = Instant::now();
+
+        let mut calls = JoinSet::new();
+        for _ in 0..10 {
+            let client = client.clone();
+            calls.spawn(async move {
+                client
+                    .call(|_| async { sleep(Duration::from_secs(3)).await })
+                    .await
+            });
+        }
+        calls.join_all().await;
+
+        // the last call starts after 9 intervals of 200ms and takes 3s itself
+        assert_eq!(start.elapsed(), Duration::from_millis(1800 + 3000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_concurrent() {
+        let client = RateLimitedClient::builder(AtomicUsize::new(0))
+            .quota(Quota::per_second(100))
+            .max_concurrent(2)
+            .build();
+
+        let mut calls = JoinSet::new();
+        for _ in 0..6 {
+            let client = client.clone();
+            calls.spawn(async move {
+                client
+                    .call(|in_flight| async move {
+                        let running = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        sleep(Duration::from_millis(100)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        running
+                    })
+                    .await
+            });
+        }
+
+        let max_running = calls.join_all().await.into_iter().max();
+        assert_eq!(max_running, Some(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[allow(deprecated)]
+    async fn test_deprecated_new() {
+        let client = RateLimitedClient::new((), 2);
+        let start = Instant::now();
+
+        for _ in 0..4 {
+            client.call(|_| async {}).await;
+        }
+
+        assert_eq!(client.concurrency_limit(), Some(2));
+        assert_eq!(start.elapsed(), Duration::from_millis(1000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_call_weighted() {
+        let client = RateLimitedClient::per_second((), 10);
+        let start = Instant::now();
+
+        client.call_weighted(10, |_| async {}).await;
+        client.call_weighted(5, |_| async {}).await;
+        client.call(|_| async {}).await;
+
+        assert_eq!(start.elapsed(), Duration::from_millis(600));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_critical_calls_get_through_saturated_quota() {
+        let client = RateLimitedClient::builder(())
+            .quota(Quota::per_second(10))
+            .reserve(Priority::Critical, 2)
+            .build();
+        let background = client.with_priority(Priority::Background);
+
+        let mut scans = JoinSet::new();
+        for _ in 0..100 {
+            let background = background.clone();
+            scans.spawn(async move { background.call(|_| async {}).await });
+        }
+        sleep(Duration::from_secs(1)).await;
+
+        let start = Instant::now();
+        client
+            .with_priority(Priority::Critical)
+            .call(|_| async {})
+            .await;
+        client
+            .with_priority(Priority::Critical)
+            .call(|_| async {})
+            .await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // the reserve refills while the background calls are still queued
+        sleep(Duration::from_millis(200)).await;
+        let start = Instant::now();
+        client
+            .with_priority(Priority::Critical)
+            .call(|_| async {})
+            .await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        scans.abort_all();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_critical_calls_get_through_saturated_concurrency() {
+        let client = RateLimitedClient::builder(())
+            .max_concurrent(4)
+            .reserve(Priority::Critical, 1)
+            .reserve(Priority::Normal, 1)
+            .build();
+
+        let mut calls = JoinSet::new();
+        for priority in [Priority::Background, Priority::Normal] {
+            for _ in 0..10 {
+                let client = client.with_priority(priority);
+                calls.spawn(async move {
+                    client
+                        .call(|_| async { sleep(Duration::from_secs(1)).await })
+                        .await
+                });
+            }
+        }
+        sleep(Duration::from_millis(100)).await;
+
+        let start = Instant::now();
+        client
+            .with_priority(Priority::Critical)
+            .call(|_| async { sleep(Duration::from_millis(10)).await })
+            .await;
+        assert_eq!(start.elapsed(), Duration::from_millis(10));
+
+        calls.join_all().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_disable_limit() {
+        let mut client = RateLimitedClient::per_second((), 1);
+        client.disable_limit();
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            client.call(|_| async {}).await;
+        }
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,340 @@
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
+
+use super::{Priority, RateLimitedClient};
+
+#[derive(Debug, thiserror::Error, Clone, Copy, PartialEq, Eq)]
+pub enum CallError {
+    #[error("no slot available within {0:?}")]
+    AcquireTimeout(Duration),
+    #[error("call did not finish within {0:?}")]
+    ExecutionTimeout(Duration),
+    #[error("call cancelled")]
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Timeouts {
+    acquire: Option<Duration>,
+    execution: Option<Duration>,
+    hedge_after: Option<Duration>,
+}
+
+/// [`RateLimitedClient`] whose calls give up after a timeout or once a cancel token is
+/// cancelled, failing with a [`CallError`], and can be hedged with a second attempt.
+///
+/// The calls go through the limits of the wrapped client, shared with its other handles.
+///
+/// Example
+/// ```rust
+/// use std::time::Duration;
+///
+/// use solar::rate_limited::{CallError, GuardedClient, Quota, RateLimitedClient};
+///
+/// struct InternalClient;
+///
+/// impl InternalClient {
+///     async fn get_slot(&self) -> u64 {
+///         42
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client = RateLimitedClient::builder(InternalClient)
+///         .quota(Quota::per_second(1))
+///         .build();
+///     let client = GuardedClient::builder(client)
+///         .acquire_timeout(Duration::from_millis(100))
+///         .execution_timeout(Duration::from_secs(5))
+///         .build();
+///
+///     let slot = client.call(|client| async move { client.get_slot().await }).await;
+///     assert_eq!(slot, Ok(42));
+///
+///     let slot = client.call(|client| async move { client.get_slot().await }).await;
+///     assert_eq!(slot, Err(CallError::AcquireTimeout(Duration::from_millis(100))));
+/// }
+/// ```
+pub struct GuardedClient<T> {
+    client: RateLimitedClient<T>,
+    timeouts: Timeouts,
+    cancel_token: Option<CancellationToken>,
+}
+
+impl<T> Clone for GuardedClient<T> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            timeouts: self.timeouts,
+            cancel_token: self.cancel_token.clone(),
+        }
+    }
+}
+
+/// Builder for a [`GuardedClient`], waiting as long as it takes by default.
+pub struct GuardedClientBuilder<T> {
+    client: RateLimitedClient<T>,
+    timeouts: Timeouts,
+    cancel_token: Option<CancellationToken>,
+}
+
+impl<T> GuardedClientBuilder<T> {
+    pub fn new(client: RateLimitedClient<T>) -> Self {
+        Self {
+            client,
+            timeouts: Timeouts::default(),
+            cancel_token: None,
+        }
+    }
+
+    /// How long a call waits for a slot before giving up.
+    pub fn acquire_timeout(mut self, acquire_timeout: Duration) -> Self {
+        self.timeouts.acquire = Some(acquire_timeout);
+        self
+    }
+
+    /// How long a call is let run once it has a slot.
+    pub fn execution_timeout(mut self, execution_timeout: Duration) -> Self {
+        self.timeouts.execution = Some(execution_timeout);
+        self
+    }
+
+    /// Fires a second attempt when the first one takes longer than `hedge_after`, returning
+    /// whichever finishes first.
+    ///
+    /// The second attempt takes a concurrency slot and quota of its own, and is skipped if
+    /// they are not free at that moment, e.g. with a `max_concurrent` of 1.
+    pub fn hedge_after(mut self, hedge_after: Duration) -> Self {
+        self.timeouts.hedge_after = Some(hedge_after);
+        self
+    }
+
+    /// Makes the calls fail with [`CallError::Cancelled`] once `cancel_token` is cancelled.
+    pub fn cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.cancel_token = Some(cancel_token);
+        self
+    }
+
+    pub fn build(self) -> GuardedClient<T> {
+        GuardedClient {
+            client: self.client,
+            timeouts: self.timeouts,
+            cancel_token: self.cancel_token,
+        }
+    }
+}
+
+impl<T> GuardedClient<T> {
+    pub fn builder(client: RateLimitedClient<T>) -> GuardedClientBuilder<T> {
+        GuardedClientBuilder::new(client)
+    }
+
+    /// The wrapped client, for calls that wait as long as it takes.
+    pub fn inner(&self) -> &RateLimitedClient<T> {
+        &self.client
+    }
+
+    /// Handle sharing the limits and timeouts of this client whose calls are made with
+    /// `priority`.
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        Self {
+            client: self.client.with_priority(priority),
+            ..self.clone()
+        }
+    }
+
+    /// Handle sharing the limits and timeouts of this client whose calls fail with
+    /// [`CallError::Cancelled`] once `cancel_token` is cancelled.
+    pub fn with_cancel_token(&self, cancel_token: CancellationToken) -> Self {
+        Self {
+            cancel_token: Some(cancel_token),
+            ..self.clone()
+        }
+    }
+
+    /// Same as [`RateLimitedClient::call`], applying the timeouts, hedging and cancel token
+    /// of this client.
+    ///
+    /// Cancelled or timed out while waiting for a slot, the call has not used up any quota.
+    pub async fn call<F, Fut, R>(&self, func: F) -> Result<R, CallError>
+    where
+        F: FnMut(Arc<T>) -> Fut,
+        Fut: Future<Output = R>,
+    {
+        self.call_weighted(1, func).await
+    }
+
+    /// Same as [`GuardedClient::call`], using up `cost` units of the quota per attempt.
+    pub async fn call_weighted<F, Fut, R>(&self, cost: u32, mut func: F) -> Result<R, CallError>
+    where
+        F: FnMut(Arc<T>) -> Fut,
+        Fut: Future<Output = R>,
+    {
+        let call = async {
+            let _slot = match self.timeouts.acquire {
+                Some(limit) => timeout(limit, self.client.acquire(cost))
+                    .await
+                    .map_err(|_| CallError::AcquireTimeout(limit))?,
+                None => self.client.acquire(cost).await,
+            };
+
+            match self.timeouts.execution {
+                Some(limit) => timeout(limit, self.execute(cost, &mut func))
+                    .await
+                    .map_err(|_| CallError::ExecutionTimeout(limit)),
+                None => Ok(self.execute(cost, &mut func).await),
+            }
+        };
+
+        match &self.cancel_token {
+            Some(cancel_token) => tokio::select! {
+                biased;
+                _ = cancel_token.cancelled() => Err(CallError::Cancelled),
+                result = call => result,
+            },
+            None => call.await,
+        }
+    }
+
+    /// Runs the call, hedged with a second attempt if it is slow.
+    async fn execute<F, Fut, R>(&self, cost: u32, func: &mut F) -> R
+    where
+        F: FnMut(Arc<T>) -> Fut,
+        Fut: Future<Output = R>,
+    {
+        let first = func(Arc::clone(&self.client.client));
+        let Some(hedge_after) = self.timeouts.hedge_after else {
+            return first.await;
+        };
+
+        let mut first = pin!(first);
+        tokio::select! {
+            result = &mut first => return result,
+            _ = sleep(hedge_after) => {}
+        }
+
+        // no slot or quota to spare for a second attempt
+        let Some(_slot) = self.client.try_acquire(cost) else {
+            return first.await;
+        };
+
+        let hedge = func(Arc::clone(&self.client.client));
+        tokio::select! {
+            result = first => result,
+            result = hedge => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::rate_limited::Quota;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeouts() {
+        let client = RateLimitedClient::per_second((), 1);
+        let client = GuardedClient::builder(client)
+            .acquire_timeout(Duration::from_millis(100))
+            .execution_timeout(Duration::from_millis(500))
+            .build();
+
+        assert_eq!(client.call(|_| async {}).await, Ok(()));
+        assert_eq!(
+            client.call(|_| async {}).await,
+            Err(CallError::AcquireTimeout(Duration::from_millis(100)))
+        );
+
+        // the call that timed out has not used up the next slot
+        sleep(Duration::from_millis(900)).await;
+        let start = Instant::now();
+        let result = client
+            .call(|_| async { sleep(Duration::from_secs(1)).await })
+            .await;
+        assert_eq!(
+            result,
+            Err(CallError::ExecutionTimeout(Duration::from_millis(500)))
+        );
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_token() {
+        let cancel_token = CancellationToken::new();
+        let client = GuardedClient::builder(RateLimitedClient::per_second((), 1))
+            .cancel_token(cancel_token.clone())
+            .build();
+        client.inner().call(|_| async {}).await;
+
+        let canceller = cancel_token.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+
+        assert_eq!(client.call(|_| async {}).await, Err(CallError::Cancelled));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedged_call() {
+        let client = RateLimitedClient::builder(AtomicUsize::new(0))
+            .quota(Quota::per_second(10))
+            .build();
+        let client = GuardedClient::builder(client)
+            .hedge_after(Duration::from_millis(200))
+            .build();
+        let start = Instant::now();
+
+        // the first attempt hangs, the second one answers right away
+        let attempt = client
+            .call(|attempts| async move {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                if attempt == 0 {
+                    sleep(Duration::from_secs(10)).await;
+                }
+                attempt
+            })
+            .await;
+
+        assert_eq!(attempt, Ok(1));
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+
+        // fast calls are not hedged
+        let attempt = client
+            .call(|attempts| async move { attempts.fetch_add(1, Ordering::SeqCst) })
+            .await;
+        assert_eq!(attempt, Ok(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_hedge_needs_a_free_slot() {
+        let client = RateLimitedClient::builder(AtomicUsize::new(0))
+            .max_concurrent(1)
+            .build();
+        let client = GuardedClient::builder(client)
+            .hedge_after(Duration::from_millis(200))
+            .build();
+        let start = Instant::now();
+
+        let attempt = client
+            .call(|attempts| async move {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                sleep(Duration::from_secs(1)).await;
+                attempt
+            })
+            .await;
+
+        assert_eq!(attempt, Ok(0));
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,271 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::{Instant, sleep};
+use tokio_util::sync::CancellationToken;
+
+use super::Quota;
+
+#[derive(Debug)]
+struct State<K> {
+    // theoretical arrival time of the next request of each key, see `RateLimiter`
+    buckets: HashMap<K, Instant>,
+    global: Option<Instant>,
+}
+
+/// Rate limiter with a separate [`Quota`] for every key, e.g. an API key or a wallet
+/// address, and optionally a global ceiling over all keys together.
+///
+/// Keys that have been idle long enough to refill their burst carry no state and are
+/// dropped by [`KeyedRateLimiter::cleanup`], which [`KeyedRateLimiter::run_cleanup`] calls
+/// periodically.
+///
+/// Example
+/// ```rust
+/// use axum::extract::{Request, State};
+/// use axum::http::StatusCode;
+/// use axum::middleware::Next;
+/// use axum::response::{IntoResponse, Response};
+/// use solar::rate_limited::{KeyedRateLimiter, Quota};
+///
+/// async fn limit_api_keys(
+///     State(limiter): State<KeyedRateLimiter<String>>,
+///     request: Request,
+///     next: Next,
+/// ) -> Response {
+///     let api_key = request
+///         .headers()
+///         .get("x-api-key")
+///         .and_then(|value| value.to_str().ok())
+///         .unwrap_or_default();
+///
+///     match limiter.try_acquire(api_key) {
+///         Ok(()) => next.run(request).await,
+///         Err(_retry_in) => StatusCode::TOO_MANY_REQUESTS.into_response(),
+///     }
+/// }
+///
+/// let limiter = KeyedRateLimiter::new(Quota::per_second(5)).with_global(Quota::per_second(100));
+/// let app: axum::Router = axum::Router::new()
+///     .layer(axum::middleware::from_fn_with_state(limiter, limit_api_keys));
+/// ```
+#[derive(Debug)]
+pub struct KeyedRateLimiter<K> {
+    quota: Quota,
+    global_quota: Option<Quota>,
+    state: Arc<Mutex<State<K>>>,
+}
+
+impl<K> Clone for KeyedRateLimiter<K> {
+    fn clone(&self) -> Self {
+        Self {
+            quota: self.quota,
+            global_quota: self.global_quota,
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<K: Hash + Eq> KeyedRateLimiter<K> {
+    /// Limiter giving every key its own `quota`.
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            quota,
+            global_quota: None,
+            state: Arc::new(Mutex::new(State {
+                buckets: HashMap::new(),
+                global: None,
+            })),
+        }
+    }
+
+    /// Limit on all keys together, on top of the quota of each key.
+    pub fn with_global(mut self, global_quota: Quota) -> Self {
+        self.global_quota = Some(global_quota);
+        self
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Number of keys currently tracked.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn try_acquire<Q>(&self, key: &Q) -> Result<(), Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.try_acquire_n(key, 1)
+    }
+
+    /// Takes `cost` slots of `key` and of the global ceiling if both have them available
+    /// right now, otherwise returns how long to wait before trying again. Nothing is taken
+    /// from either when one of them is exhausted.
+    pub fn try_acquire_n<Q>(&self, key: &Q, cost: u32) -> Result<(), Duration>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        let tat = state.buckets.get(key).copied();
+        let next_tat = self.quota.next_tat(tat, cost, 0, now)?;
+        let next_global = match &self.global_quota {
+            Some(global_quota) => Some(global_quota.next_tat(state.global, cost, 0, now)?),
+            None => None,
+        };
+
+        match state.buckets.get_mut(key) {
+            Some(tat) => *tat = next_tat,
+            None => {
+                state.buckets.insert(key.to_owned(), next_tat);
+            }
+        }
+        state.global = next_global;
+        Ok(())
+    }
+
+    /// Takes `cost` slots of the global ceiling only, e.g. for a request without a key, if
+    /// they are available right now. Always succeeds without a global ceiling.
+    pub fn try_acquire_global(&self, cost: u32) -> Result<(), Duration> {
+        let Some(global_quota) = &self.global_quota else {
+            return Ok(());
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.global = Some(global_quota.next_tat(state.global, cost, 0, Instant::now())?);
+        Ok(())
+    }
+
+    /// Waits until `cost` slots of the global ceiling are available and takes them.
+    pub async fn acquire_global(&self, cost: u32) {
+        while let Err(wait) = self.try_acquire_global(cost) {
+            sleep(wait).await;
+        }
+    }
+
+    /// Waits until a slot of `key` is available and takes it.
+    pub async fn acquire<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.acquire_n(key, 1).await;
+    }
+
+    /// Waits until `cost` slots of `key` are available and takes them.
+    pub async fn acquire_n<Q>(&self, key: &Q, cost: u32)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        while let Err(wait) = self.try_acquire_n(key, cost) {
+            sleep(wait).await;
+        }
+    }
+
+    /// Drops the keys that have refilled their burst, returning how many were dropped.
+    pub fn cleanup(&self) -> usize {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        let len = state.buckets.len();
+        state.buckets.retain(|_, tat| *tat > now);
+        len - state.buckets.len()
+    }
+
+    /// Calls [`KeyedRateLimiter::cleanup`] every `interval` until cancelled.
+    pub async fn run_cleanup(self, interval: Duration, cancel_token: CancellationToken) {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = cancel_token.cancelled() => return,
+            }
+
+            let _removed = self.cleanup();
+            #[cfg(feature = "log")]
+            log::debug!(client = "KeyedRateLimiter"; "dropped {_removed} idle keys");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keys_are_separate() {
+        let limiter = KeyedRateLimiter::<String>::new(Quota::per_second(2));
+
+        assert!(limiter.try_acquire("alice").is_ok());
+        assert!(limiter.try_acquire("alice").is_ok());
+        assert_eq!(
+            limiter.try_acquire("alice"),
+            Err(Duration::from_millis(500))
+        );
+        assert!(limiter.try_acquire("bob").is_ok());
+        assert_eq!(limiter.len(), 2);
+
+        let start = Instant::now();
+        limiter.acquire("alice").await;
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_global_ceiling() {
+        let limiter =
+            KeyedRateLimiter::<u64>::new(Quota::per_second(2)).with_global(Quota::per_second(3));
+
+        assert!(limiter.try_acquire(&1).is_ok());
+        assert!(limiter.try_acquire(&2).is_ok());
+        assert!(limiter.try_acquire(&3).is_ok());
+        assert!(limiter.try_acquire(&4).is_err());
+
+        // a key over its own quota does not use up the global one
+        sleep(Duration::from_millis(334)).await;
+        assert!(limiter.try_acquire_n(&1, 2).is_err());
+        assert!(limiter.try_acquire(&4).is_ok());
+        assert_eq!(limiter.len(), 4);
+
+        // requests without a key share the global ceiling
+        assert!(limiter.try_acquire_global(1).is_err());
+        sleep(Duration::from_millis(334)).await;
+        assert!(limiter.try_acquire_global(1).is_ok());
+        assert!(limiter.try_acquire(&5).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cleanup() {
+        let limiter = KeyedRateLimiter::<String>::new(Quota::per_second(10));
+        let cancel_token = CancellationToken::new();
+        tokio::spawn(
+            limiter
+                .clone()
+                .run_cleanup(Duration::from_secs(1), cancel_token.clone()),
+        );
+
+        limiter.try_acquire_n("alice", 10).unwrap();
+        limiter.try_acquire_n("bob", 5).unwrap();
+        sleep(Duration::from_millis(600)).await;
+        assert_eq!(limiter.cleanup(), 1);
+
+        sleep(Duration::from_millis(500)).await;
+        assert!(limiter.is_empty());
+        cancel_token.cancel();
+    }
+}
@@ -0,0 +1,435 @@
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum::http::HeaderValue;
+use axum::http::header::RETRY_AFTER;
+use axum::response::{IntoResponse, Response};
+use tower::{Layer, Service};
+
+use super::{KeyedRateLimiter, Quota, RateLimiter};
+use crate::axum::ErrorResponse;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// What a rate limited service does with a request over the limit.
+pub trait Rejection<Res>: Clone {
+    /// Response returned instead of calling the service, `None` to wait for a slot instead.
+    fn reject(&self, retry_in: Duration) -> Option<Res>;
+}
+
+/// Rejects with an [`ErrorResponse`] 429 and a `Retry-After` header.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TooManyRequests;
+
+impl Rejection<Response> for TooManyRequests {
+    fn reject(&self, retry_in: Duration) -> Option<Response> {
+        let mut response = ErrorResponse::too_many_requests().into_response();
+        let retry_after = retry_in.as_secs() + u64::from(retry_in.subsec_nanos() > 0);
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+
+        Some(response)
+    }
+}
+
+/// Holds the request until a slot is available, e.g. for an outgoing client.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wait;
+
+impl<Res> Rejection<Res> for Wait {
+    fn reject(&self, _retry_in: Duration) -> Option<Res> {
+        None
+    }
+}
+
+impl<F, Res> Rejection<Res> for F
+where
+    F: Fn(Duration) -> Res + Clone,
+{
+    fn reject(&self, retry_in: Duration) -> Option<Res> {
+        Some(self(retry_in))
+    }
+}
+
+/// [`Layer`] limiting all the requests of a service together with a [`RateLimiter`].
+///
+/// Requests over the limit get the [`Rejection`], by default a 429 [`ErrorResponse`], or
+/// wait for a slot with [`Wait`].
+///
+/// Example
+/// ```rust
+/// use axum::Router;
+/// use axum::routing::get;
+/// use solar::rate_limited::{Quota, RateLimitLayer};
+///
+/// let app: Router = Router::new()
+///     .route("/", get(|| async { "hello" }))
+///     .layer(RateLimitLayer::new(Quota::per_second(100)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer<J = TooManyRequests> {
+    limiter: Arc<RateLimiter>,
+    rejection: J,
+}
+
+impl RateLimitLayer {
+    pub fn new(quota: Quota) -> Self {
+        Self::with_limiter(Arc::new(RateLimiter::new(quota)))
+    }
+
+    /// Layer sharing `limiter`, e.g. with a [`super::RateLimitedClient`] or other services.
+    pub fn with_limiter(limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            limiter,
+            rejection: TooManyRequests,
+        }
+    }
+}
+
+impl<J> RateLimitLayer<J> {
+    pub fn with_rejection<R>(self, rejection: R) -> RateLimitLayer<R> {
+        RateLimitLayer {
+            limiter: self.limiter,
+            rejection,
+        }
+    }
+}
+
+impl<S, J: Clone> Layer<S> for RateLimitLayer<J> {
+    type Service = RateLimit<S, J>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: Arc::clone(&self.limiter),
+            rejection: self.rejection.clone(),
+        }
+    }
+}
+
+/// Service created by a [`RateLimitLayer`].
+#[derive(Debug, Clone)]
+pub struct RateLimit<S, J> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+    rejection: J,
+}
+
+impl<S, Req, J> Service<Req> for RateLimit<S, J>
+where
+    S: Service<Req> + Clone + Send + 'static,
+    S::Response: Send,
+    S::Future: Send,
+    Req: Send + 'static,
+    J: Rejection<S::Response> + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let limiter = Arc::clone(&self.limiter);
+        let rejection = self.rejection.clone();
+        // the clone is not ready yet, the instance polled ready handles this request
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            if let Err(retry_in) = limiter.try_acquire() {
+                match rejection.reject(retry_in) {
+                    Some(response) => return Ok(response),
+                    None => limiter.acquire().await,
+                }
+            }
+
+            inner.call(request).await
+        })
+    }
+}
+
+/// What a [`KeyedRateLimit`] does with the requests `key` extracts no key from, on top of
+/// the global ceiling of the limiter which they always count against.
+#[derive(Debug, Clone)]
+pub enum Keyless<K> {
+    /// No limit of their own, only the global ceiling.
+    GlobalOnly,
+    /// Rejected right away with a zero `retry_in`. With [`Wait`], which never rejects, they
+    /// wait for the global ceiling only.
+    Reject,
+    /// Limited together like requests with this key.
+    Shared(K),
+}
+
+/// [`Layer`] limiting the requests of every key separately with a [`KeyedRateLimiter`], the
+/// key being extracted from the request by `key`. Requests without a key are handled as
+/// set with [`KeyedRateLimitLayer::with_keyless`], by default only counting against the
+/// global ceiling.
+///
+/// Example
+/// ```rust
+/// use axum::Router;
+/// use axum::extract::Request;
+/// use axum::routing::get;
+/// use solar::rate_limited::{KeyedRateLimitLayer, KeyedRateLimiter, Quota};
+///
+/// let limiter = KeyedRateLimiter::new(Quota::per_second(5)).with_global(Quota::per_second(100));
+/// let app: Router = Router::new()
+///     .route("/", get(|| async { "hello" }))
+///     .layer(KeyedRateLimitLayer::new(limiter, |request: &Request| {
+///         request
+///             .headers()
+///             .get("x-api-key")
+///             .and_then(|value| value.to_str().ok())
+///             .map(str::to_string)
+///     }));
+/// ```
+#[derive(Debug, Clone)]
+pub struct KeyedRateLimitLayer<K, X, J = TooManyRequests> {
+    limiter: KeyedRateLimiter<K>,
+    key: X,
+    keyless: Keyless<K>,
+    rejection: J,
+}
+
+impl<K, X> KeyedRateLimitLayer<K, X> {
+    pub fn new(limiter: KeyedRateLimiter<K>, key: X) -> Self {
+        Self {
+            limiter,
+            key,
+            keyless: Keyless::GlobalOnly,
+            rejection: TooManyRequests,
+        }
+    }
+}
+
+impl<K, X, J> KeyedRateLimitLayer<K, X, J> {
+    pub fn with_rejection<R>(self, rejection: R) -> KeyedRateLimitLayer<K, X, R> {
+        KeyedRateLimitLayer {
+            limiter: self.limiter,
+            key: self.key,
+            keyless: self.keyless,
+            rejection,
+        }
+    }
+
+    pub fn with_keyless(mut self, keyless: Keyless<K>) -> Self {
+        self.keyless = keyless;
+        self
+    }
+}
+
+impl<S, K: Clone, X: Clone, J: Clone> Layer<S> for KeyedRateLimitLayer<K, X, J> {
+    type Service = KeyedRateLimit<S, K, X, J>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        KeyedRateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+            key: self.key.clone(),
+            keyless: self.keyless.clone(),
+            rejection: self.rejection.clone(),
+        }
+    }
+}
+
+/// Service created by a [`KeyedRateLimitLayer`].
+#[derive(Debug, Clone)]
+pub struct KeyedRateLimit<S, K, X, J> {
+    inner: S,
+    limiter: KeyedRateLimiter<K>,
+    key: X,
+    keyless: Keyless<K>,
+    rejection: J,
+}
+
+impl<S, Req, K, X, J> Service<Req> for KeyedRateLimit<S, K, X, J>
+where
+    S: Service<Req> + Clone + Send + 'static,
+    S::Response: Send,
+    S::Future: Send,
+    Req: Send + 'static,
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    X: Fn(&Req) -> Option<K>,
+    J: Rejection<S::Response> + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<S::Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let key = (self.key)(&request);
+        let keyless = key.is_none().then(|| self.keyless.clone());
+        let limiter = self.limiter.clone();
+        let rejection = self.rejection.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let key = match keyless {
+                None => key,
+                Some(Keyless::GlobalOnly) => None,
+                Some(Keyless::Reject) => {
+                    if let Some(response) = rejection.reject(Duration::ZERO) {
+                        return Ok(response);
+                    }
+                    None
+                }
+                Some(Keyless::Shared(key)) => Some(key),
+            };
+
+            let acquired = match &key {
+                Some(key) => limiter.try_acquire(key),
+                None => limiter.try_acquire_global(1),
+            };
+            if let Err(retry_in) = acquired {
+                match (rejection.reject(retry_in), &key) {
+                    (Some(response), _) => return Ok(response),
+                    (None, Some(key)) => limiter.acquire(key).await,
+                    (None, None) => limiter.acquire_global(1).await,
+                }
+            }
+
+            inner.call(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::Router;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use tokio::time::{Instant, sleep};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn status(app: &Router, api_key: &str) -> (StatusCode, Option<HeaderValue>) {
+        let request = Request::get("/")
+            .header("x-api-key", api_key)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+
+        (
+            response.status(),
+            response.headers().get(RETRY_AFTER).cloned(),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rejects_over_quota() {
+        let app = Router::new()
+            .route("/", get(|| async { "hello" }))
+            .layer(RateLimitLayer::new(Quota::per_minute(2)));
+
+        assert_eq!(status(&app, "").await.0, StatusCode::OK);
+        assert_eq!(status(&app, "").await.0, StatusCode::OK);
+        assert_eq!(
+            status(&app, "").await,
+            (StatusCode::TOO_MANY_REQUESTS, Some(HeaderValue::from(30)))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keyed() {
+        let limiter = KeyedRateLimiter::new(Quota::per_second(1));
+        let app = Router::new().route("/", get(|| async { "hello" })).layer(
+            KeyedRateLimitLayer::new(limiter, |request: &Request| {
+                request
+                    .headers()
+                    .get("x-api-key")
+                    .and_then(|value| value.to_str().ok())
+                    .filter(|api_key| !api_key.is_empty())
+                    .map(str::to_string)
+            })
+            .with_rejection(|_| StatusCode::IM_A_TEAPOT.into_response()),
+        );
+
+        assert_eq!(status(&app, "alice").await.0, StatusCode::OK);
+        assert_eq!(status(&app, "alice").await.0, StatusCode::IM_A_TEAPOT);
+        assert_eq!(status(&app, "bob").await.0, StatusCode::OK);
+        for _ in 0..3 {
+            assert_eq!(status(&app, "").await.0, StatusCode::OK);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keyless() {
+        let limiter = KeyedRateLimiter::new(Quota::per_second(2)).with_global(Quota::per_second(3));
+        let api_key = |request: &Request| {
+            request
+                .headers()
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+                .filter(|api_key| !api_key.is_empty())
+                .map(str::to_string)
+        };
+        let app = |keyless| {
+            Router::new()
+                .route("/", get(|| async { "hello" }))
+                .layer(KeyedRateLimitLayer::new(limiter.clone(), api_key).with_keyless(keyless))
+        };
+
+        // requests without a key are throttled by the global ceiling
+        let global_only = app(Keyless::GlobalOnly);
+        assert_eq!(status(&global_only, "alice").await.0, StatusCode::OK);
+        assert_eq!(status(&global_only, "").await.0, StatusCode::OK);
+        assert_eq!(status(&global_only, "").await.0, StatusCode::OK);
+        assert_eq!(
+            status(&global_only, "").await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            status(&global_only, "bob").await.0,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        sleep(Duration::from_secs(1)).await;
+        let reject = app(Keyless::Reject);
+        assert_eq!(
+            status(&reject, "").await,
+            (StatusCode::TOO_MANY_REQUESTS, Some(HeaderValue::from(0)))
+        );
+        assert_eq!(status(&reject, "alice").await.0, StatusCode::OK);
+
+        sleep(Duration::from_secs(1)).await;
+        let shared = app(Keyless::Shared("anonymous".to_string()));
+        assert_eq!(status(&shared, "").await.0, StatusCode::OK);
+        assert_eq!(status(&shared, "").await.0, StatusCode::OK);
+        assert_eq!(status(&shared, "").await.0, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status(&shared, "alice").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait() {
+        let service = tower::service_fn(|request: u32| async move { Ok::<_, Infallible>(request) });
+        let service = RateLimitLayer::new(Quota::per_second(10).with_burst(1))
+            .with_rejection(Wait)
+            .layer(service);
+        let start = Instant::now();
+
+        for request in 0..5 {
+            assert_eq!(service.clone().oneshot(request).await, Ok(request));
+        }
+
+        assert_eq!(start.elapsed(), Duration::from_millis(400));
+    }
+}
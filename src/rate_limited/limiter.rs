@@ -0,0 +1,230 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::{Instant, sleep};
+
+/// Number of requests allowed per `period`, with up to `burst` of them at once.
+///
+/// The fields are only set through the constructors, which reject an empty rate or period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    rate: u32,
+    period: Duration,
+    burst: u32,
+}
+
+impl Quota {
+    /// `rate` requests per `period`, evenly spread, with a burst of `rate`.
+    ///
+    /// Panics if `rate` is zero or `period` is empty.
+    pub fn new(rate: u32, period: Duration) -> Self {
+        assert!(rate > 0, "quota rate must be positive");
+        assert!(!period.is_zero(), "quota period must be positive");
+
+        Self {
+            rate,
+            period,
+            burst: rate,
+        }
+    }
+
+    pub fn per_second(rate: u32) -> Self {
+        Self::new(rate, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(rate: u32) -> Self {
+        Self::new(rate, Duration::from_secs(60))
+    }
+
+    /// Maximum number of requests let through at once after an idle period.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    /// Time between two requests at the sustained rate.
+    pub(super) fn emission_interval(&self) -> Duration {
+        self.period / self.rate
+    }
+
+    /// GCRA step: the theoretical arrival time after spending `cost` on a limiter at `tat`,
+    /// or how long to wait if that goes over the burst minus the `reserved` slots.
+    pub(super) fn next_tat(
+        &self,
+        tat: Option<Instant>,
+        cost: u32,
+        reserved: u32,
+        now: Instant,
+    ) -> Result<Instant, Duration> {
+        let interval = self.emission_interval();
+        let tolerance = interval * self.burst.saturating_sub(reserved).max(cost);
+
+        let next_tat = tat.map_or(now, |tat| tat.max(now)) + interval * cost;
+        let allowed_at = next_tat.checked_sub(tolerance).unwrap_or(now);
+
+        if allowed_at > now {
+            return Err(allowed_at - now);
+        }
+        Ok(next_tat)
+    }
+}
+
+/// GCRA rate limiter, equivalent to a token bucket of `burst` tokens refilled at `rate`
+/// per `period`, but keeping a single timestamp as its state.
+///
+/// Example
+/// ```rust
+/// use solar::rate_limited::{Quota, RateLimiter};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let limiter = RateLimiter::new(Quota::per_second(10).with_burst(2));
+///
+/// limiter.acquire().await;
+/// limiter.acquire().await;
+/// assert!(limiter.try_acquire().is_err());
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    quota: Quota,
+    // theoretical arrival time of the next request at the sustained rate
+    tat: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(quota: Quota) -> Self {
+        Self {
+            quota,
+            tat: Mutex::new(None),
+        }
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Takes a slot if one is available right now, otherwise returns how long to wait
+    /// before trying again.
+    pub fn try_acquire(&self) -> Result<(), Duration> {
+        self.try_acquire_n(1)
+    }
+
+    /// Takes `cost` slots at once if they are available right now, otherwise returns how
+    /// long to wait before trying again.
+    ///
+    /// A `cost` above the burst is let through once the limiter is fully idle, and the calls
+    /// after it wait until the excess is paid back.
+    pub fn try_acquire_n(&self, cost: u32) -> Result<(), Duration> {
+        self.try_acquire_reserved(cost, 0)
+    }
+
+    /// Same as [`RateLimiter::try_acquire_n`], leaving the last `reserved` slots of the burst
+    /// to callers reserving fewer, e.g. callers of a higher priority.
+    pub fn try_acquire_reserved(&self, cost: u32, reserved: u32) -> Result<(), Duration> {
+        let mut tat = self.tat.lock().unwrap();
+        *tat = Some(self.quota.next_tat(*tat, cost, reserved, Instant::now())?);
+        Ok(())
+    }
+
+    /// Waits until a slot is available and takes it.
+    ///
+    /// Cancellation safe: a caller dropped while waiting has not used up any slot.
+    pub async fn acquire(&self) {
+        self.acquire_n(1).await;
+    }
+
+    /// Waits until `cost` slots are available and takes them, see
+    /// [`RateLimiter::try_acquire_n`].
+    pub async fn acquire_n(&self, cost: u32) {
+        self.acquire_reserved(cost, 0).await;
+    }
+
+    /// Waits until `cost` slots are available on top of the `reserved` ones and takes them,
+    /// see [`RateLimiter::try_acquire_reserved`].
+    pub async fn acquire_reserved(&self, cost: u32, reserved: u32) {
+        while let Err(wait) = self.try_acquire_reserved(cost, reserved) {
+            sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sustained_rate() {
+        let limiter = RateLimiter::new(Quota::per_second(4).with_burst(1));
+        let start = Instant::now();
+
+        for _ in 0..9 {
+            limiter.acquire().await;
+        }
+
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_refills() {
+        let limiter = RateLimiter::new(Quota::per_second(2).with_burst(3));
+
+        for _ in 0..3 {
+            assert!(limiter.try_acquire().is_ok());
+        }
+        assert_eq!(limiter.try_acquire(), Err(Duration::from_millis(500)));
+
+        // a full period of idling refills two slots, not the whole burst
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(limiter.try_acquire().is_ok());
+        assert!(limiter.try_acquire().is_ok());
+        assert!(limiter.try_acquire().is_err());
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        for _ in 0..3 {
+            assert!(limiter.try_acquire().is_ok());
+        }
+        assert!(limiter.try_acquire().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_weighted() {
+        let limiter = RateLimiter::new(Quota::per_second(10));
+
+        assert!(limiter.try_acquire_n(8).is_ok());
+        assert_eq!(limiter.try_acquire_n(5), Err(Duration::from_millis(300)));
+        assert!(limiter.try_acquire_n(2).is_ok());
+
+        // more than the burst goes through once idle, then has to be paid back
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(limiter.try_acquire_n(25).is_ok());
+        assert_eq!(limiter.try_acquire(), Err(Duration::from_millis(1600)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reserved() {
+        let limiter = RateLimiter::new(Quota::per_second(10));
+
+        for _ in 0..7 {
+            assert!(limiter.try_acquire_reserved(1, 3).is_ok());
+        }
+        assert_eq!(
+            limiter.try_acquire_reserved(1, 3),
+            Err(Duration::from_millis(100))
+        );
+        assert!(limiter.try_acquire_n(3).is_ok());
+        assert!(limiter.try_acquire().is_err());
+    }
+}
@@ -0,0 +1,34 @@
+pub mod adaptive;
+pub mod circuit_breaker;
+pub mod client;
+pub mod guarded;
+pub mod keyed;
+#[cfg(feature = "axum")]
+pub mod layer;
+pub mod limiter;
+#[cfg(feature = "trx_factory")]
+pub mod pg_limiter;
+pub mod pool;
+pub mod priority;
+pub mod retry;
+#[cfg(feature = "solana")]
+pub mod rpc_sender;
+
+pub use adaptive::AdaptiveConcurrency;
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerBuilder, CircuitError, CircuitState};
+pub use client::{RateLimitedClient, RateLimitedClientBuilder};
+pub use guarded::{CallError, GuardedClient, GuardedClientBuilder};
+pub use keyed::KeyedRateLimiter;
+#[cfg(feature = "axum")]
+pub use layer::{
+    KeyedRateLimit, KeyedRateLimitLayer, Keyless, RateLimit, RateLimitLayer, Rejection,
+    TooManyRequests, Wait,
+};
+pub use limiter::{Quota, RateLimiter};
+#[cfg(feature = "trx_factory")]
+pub use pg_limiter::PgRateLimiter;
+pub use pool::{ClientPool, ClientPoolBuilder, EndpointStatus, Routing};
+pub use priority::Priority;
+pub use retry::RetryPolicy;
+#[cfg(feature = "solana")]
+pub use rpc_sender::{MethodCosts, RateLimitedSender};
@@ -0,0 +1,387 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use eyre::Context;
+use sqlx::PgPool;
+use tokio::time::{Instant, sleep};
+
+use super::{Quota, RateLimiter};
+
+const DEFAULT_TABLE: &str = "solar_rate_limits";
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Tokens taken from the shared quota at once, usable until `expires_at`.
+#[derive(Debug)]
+struct Grant {
+    tokens: u32,
+    expires_at: Instant,
+}
+
+/// Rate limiter sharing one [`Quota`] between every process using the same `key`, e.g.
+/// several bots calling the same RPC key, coordinated through a Postgres table.
+///
+/// The table keeps the GCRA state of each key (see [`RateLimiter`]) against the database
+/// clock. Instead of a query per call, each process takes up to `batch` tokens at once and
+/// hands them out locally; tokens left unused for a whole period are given up, so a
+/// process never bursts with an old grant. A larger batch means fewer queries but a less
+/// even split of the quota between processes.
+///
+/// When the database cannot be reached calls fall back to a local limiter with the whole
+/// quota, so the combined rate may exceed it until the database is back. After a failed
+/// query the database is left alone for `backoff`, so that the calls do not all wait for
+/// the pool to time out.
+///
+/// Example
+/// ```rust,no_run
+/// use std::sync::Arc;
+///
+/// use solar::rate_limited::{PgRateLimiter, Quota, RateLimitedClient};
+///
+/// # async fn run(pool: sqlx::PgPool) -> eyre::Result<()> {
+/// let limiter = PgRateLimiter::new(pool, "helius", Quota::per_second(50)).with_batch(5);
+/// limiter.migrate().await?;
+///
+/// let client = RateLimitedClient::builder(())
+///     .shared_limiter(Arc::new(limiter))
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PgRateLimiter {
+    pool: PgPool,
+    table: String,
+    key: String,
+    quota: Quota,
+    batch: u32,
+    backoff: Duration,
+    // oldest first, each grant expiring a period after it was taken
+    grants: Mutex<VecDeque<Grant>>,
+    // one query at a time per process, the other callers wait for its grant
+    fetching: tokio::sync::Mutex<()>,
+    retry_db_at: Mutex<Option<Instant>>,
+    fallback: RateLimiter,
+}
+
+impl PgRateLimiter {
+    /// Limiter taking a tenth of the burst of `quota` at once.
+    ///
+    /// Panics if `quota` allows more than one request per nanosecond.
+    pub fn new(pool: PgPool, key: impl Into<String>, quota: Quota) -> Self {
+        assert!(
+            !quota.emission_interval().is_zero(),
+            "quota rate must be at most one request per nanosecond"
+        );
+
+        Self {
+            pool,
+            table: DEFAULT_TABLE.to_string(),
+            key: key.into(),
+            quota,
+            batch: (quota.burst() / 10).max(1),
+            backoff: DEFAULT_BACKOFF,
+            grants: Mutex::new(VecDeque::new()),
+            fetching: tokio::sync::Mutex::new(()),
+            retry_db_at: Mutex::new(None),
+            fallback: RateLimiter::new(quota),
+        }
+    }
+
+    /// Table used to store the state of the keys. The name is put into the queries as is,
+    /// so it must be a trusted identifier.
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.table = table.into();
+        self
+    }
+
+    /// Tokens taken from the shared quota at once.
+    pub fn with_batch(mut self, batch: u32) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+
+    /// How long calls use the local fallback after a failed query, 5s by default.
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Creates the rate limit table if it does not exist yet.
+    pub async fn migrate(&self) -> eyre::Result<()> {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                key TEXT PRIMARY KEY,
+                tat TIMESTAMPTZ NOT NULL
+            )",
+            table = self.table
+        ))
+        .execute(&self.pool)
+        .await
+        .context("failed to create rate limit table")?;
+
+        Ok(())
+    }
+
+    /// Waits until `cost` tokens are available and takes them.
+    pub async fn acquire_n(&self, cost: u32) {
+        if !self.take_local(cost) && !self.acquire_shared(cost).await {
+            self.fallback.acquire_n(cost).await;
+        }
+    }
+
+    /// Takes `cost` tokens from the shared quota, returns false if the database is down.
+    async fn acquire_shared(&self, cost: u32) -> bool {
+        if self.db_down() {
+            return false;
+        }
+
+        let _fetching = self.fetching.lock().await;
+        loop {
+            // granted to the caller that was fetching before us
+            if self.take_local(cost) {
+                return true;
+            }
+            if self.db_down() {
+                return false;
+            }
+
+            let needed = cost.saturating_sub(self.local_tokens()).max(1);
+            match self.grant(needed, self.batch.max(needed)).await {
+                Ok(Ok(granted)) => self.add_local(granted),
+                Ok(Err(wait)) => sleep(wait).await,
+                Err(_err) => {
+                    #[cfg(feature = "log")]
+                    log::error!(client = "PgRateLimiter"; "failed to get tokens: {_err:?}");
+
+                    *self.retry_db_at.lock().unwrap() = Some(Instant::now() + self.backoff);
+                    return false;
+                }
+            }
+        }
+    }
+
+    fn db_down(&self) -> bool {
+        let retry_db_at = *self.retry_db_at.lock().unwrap();
+        retry_db_at.is_some_and(|at| at > Instant::now())
+    }
+
+    fn local_tokens(&self) -> u32 {
+        let mut grants = self.grants.lock().unwrap();
+        Self::drop_expired(&mut grants);
+        grants.iter().map(|grant| grant.tokens).sum()
+    }
+
+    /// Takes `cost` of the tokens already granted to this process, without a query.
+    pub(super) fn take_local(&self, mut cost: u32) -> bool {
+        let mut grants = self.grants.lock().unwrap();
+        Self::drop_expired(&mut grants);
+        if grants.iter().map(|grant| grant.tokens).sum::<u32>() < cost {
+            return false;
+        }
+
+        while let Some(grant) = grants.front_mut() {
+            let taken = grant.tokens.min(cost);
+            grant.tokens -= taken;
+            cost -= taken;
+            if grant.tokens > 0 {
+                break;
+            }
+            grants.pop_front();
+        }
+        true
+    }
+
+    fn add_local(&self, tokens: u32) {
+        self.grants.lock().unwrap().push_back(Grant {
+            tokens,
+            expires_at: Instant::now() + self.quota.period(),
+        });
+    }
+
+    fn drop_expired(grants: &mut VecDeque<Grant>) {
+        let now = Instant::now();
+        while grants.front().is_some_and(|grant| grant.expires_at <= now) {
+            grants.pop_front();
+        }
+    }
+
+    /// Takes between `min` and `max` tokens from the shared quota, or returns how long to
+    /// wait for `min` of them.
+    async fn grant(&self, min: u32, max: u32) -> eyre::Result<Result<u32, Duration>> {
+        // in nanoseconds, wide enough for a long period times a large burst
+        let interval = self.quota.emission_interval().as_nanos() as i128;
+        let tolerance = interval * i128::from(self.quota.burst().max(min));
+
+        let mut trx = self
+            .pool
+            .begin()
+            .await
+            .context("failed to begin transaction")?;
+
+        sqlx::query(&format!(
+            "INSERT INTO {table} (key, tat) VALUES ($1, now()) ON CONFLICT (key) DO NOTHING",
+            table = self.table
+        ))
+        .bind(&self.key)
+        .execute(&mut *trx)
+        .await
+        .context("failed to insert rate limit key")?;
+
+        // how far the theoretical arrival time is ahead of now, in microseconds
+        let (ahead,) = sqlx::query_as::<_, (i64,)>(&format!(
+            "SELECT (EXTRACT(EPOCH FROM GREATEST(tat, now()) - now()) * 1000000)::bigint
+            FROM {table}
+            WHERE key = $1
+            FOR UPDATE",
+            table = self.table
+        ))
+        .bind(&self.key)
+        .fetch_one(&mut *trx)
+        .await
+        .context("failed to lock rate limit key")?;
+
+        let ahead = i128::from(ahead) * 1000;
+        let available = ((tolerance - ahead) / interval).clamp(0, i128::from(u32::MAX)) as u32;
+        if available < min {
+            let wait = ahead + interval * i128::from(min) - tolerance;
+            return Ok(Err(Duration::from_nanos(wait.max(0) as u64)));
+        }
+
+        let granted = available.min(max);
+        sqlx::query(&format!(
+            "UPDATE {table}
+            SET tat = GREATEST(tat, now()) + $2::float8 * interval '1 microsecond'
+            WHERE key = $1",
+            table = self.table
+        ))
+        .bind(&self.key)
+        .bind((interval * i128::from(granted)) as f64 / 1000.0)
+        .execute(&mut *trx)
+        .await
+        .context("failed to update rate limit key")?;
+
+        trx.commit().await.context("failed to commit transaction")?;
+
+        Ok(Ok(granted))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sqlx::postgres::PgPoolOptions;
+    use tokio::task::JoinSet;
+
+    use super::*;
+
+    async fn connect(table: &str) -> PgPool {
+        let url = std::env::var("DATABASE_URL")
+            .unwrap_or("postgres://postgres@127.0.0.1/postgres".to_string());
+        let pool = PgPool::connect(&url).await.unwrap();
+
+        sqlx::query(&format!("DROP TABLE IF EXISTS {table}"))
+            .execute(&pool)
+            .await
+            .unwrap();
+        PgRateLimiter::new(pool.clone(), "rpc", Quota::per_second(1))
+            .with_table(table)
+            .migrate()
+            .await
+            .unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local postgres"]
+    async fn test_quota_is_shared() {
+        let table = "solar_rate_limits_test_shared";
+        let pool = connect(table).await;
+        let quota = Quota::per_second(20);
+        // two processes with their own local state and batch size
+        let processes = [
+            PgRateLimiter::new(pool.clone(), "rpc", quota).with_table(table),
+            PgRateLimiter::new(pool.clone(), "rpc", quota)
+                .with_table(table)
+                .with_batch(5),
+        ];
+        let start = std::time::Instant::now();
+
+        let mut calls = JoinSet::new();
+        for limiter in processes.map(Arc::new) {
+            for _ in 0..20 {
+                let limiter = Arc::clone(&limiter);
+                calls.spawn(async move { limiter.acquire_n(1).await });
+            }
+        }
+        calls.join_all().await;
+
+        // a burst of 20, then 20 more at 20 per second
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(900), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local postgres"]
+    async fn test_grants_are_batched() {
+        let table = "solar_rate_limits_test_batched";
+        let limiter = PgRateLimiter::new(connect(table).await, "rpc", Quota::per_second(100))
+            .with_table(table)
+            .with_batch(10);
+
+        limiter.acquire_n(1).await;
+        assert_eq!(limiter.local_tokens(), 9);
+        limiter.acquire_n(4).await;
+        assert_eq!(limiter.local_tokens(), 5);
+
+        // more than what is left locally only takes the difference, on top of the batch
+        limiter.acquire_n(12).await;
+        assert_eq!(limiter.local_tokens(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_falls_back_to_local_quota() {
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://postgres@127.0.0.1:1/postgres")
+            .unwrap();
+        let limiter = PgRateLimiter::new(pool, "rpc", Quota::per_second(10).with_burst(1));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire_n(1).await;
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        // the database is not tried again until the backoff is over
+        assert!(limiter.db_down());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grants_expire_separately() {
+        let pool = PgPool::connect_lazy("postgres://postgres@127.0.0.1/postgres").unwrap();
+        let limiter = PgRateLimiter::new(pool, "rpc", Quota::per_second(10));
+
+        limiter.add_local(5);
+        sleep(Duration::from_millis(600)).await;
+        limiter.add_local(3);
+        assert!(limiter.take_local(2));
+        assert_eq!(limiter.local_tokens(), 6);
+
+        // the older grant does not live longer because of the newer one
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(limiter.local_tokens(), 3);
+        assert!(limiter.take_local(3));
+        assert_eq!(limiter.local_tokens(), 0);
+    }
+}
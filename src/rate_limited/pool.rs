@@ -0,0 +1,492 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::RateLimitedClient;
+
+/// Order in which a [`ClientPool`] tries its endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Routing {
+    /// Each call starts at the endpoint after the one the previous call started at.
+    #[default]
+    RoundRobin,
+    /// Endpoints with the fewest calls in progress first.
+    LeastLoaded,
+    /// Endpoints with the lowest priority value first, the others only on failover.
+    Priority,
+}
+
+/// Health of an endpoint of a [`ClientPool`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointStatus {
+    pub name: String,
+    pub healthy: bool,
+    pub in_flight: usize,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+impl Health {
+    /// Forgets the failures behind a quarantine once it is over, so that it takes another
+    /// `unhealthy_after` failures to quarantine the endpoint again.
+    fn end_quarantine(&mut self, now: Instant) {
+        if self.unhealthy_until.is_some_and(|until| until <= now) {
+            *self = Health::default();
+        }
+    }
+}
+
+struct Endpoint<T> {
+    name: String,
+    priority: u32,
+    client: RateLimitedClient<T>,
+    in_flight: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+impl<T> Endpoint<T> {
+    fn is_healthy(&self, now: Instant) -> bool {
+        let mut health = self.health.lock().unwrap();
+        health.end_quarantine(now);
+        health.unhealthy_until.is_none()
+    }
+}
+
+/// Decrements the in-flight count of an endpoint when the call finishes or is dropped.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn new(in_flight: &'a AtomicUsize) -> Self {
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        Self(in_flight)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Inner<T> {
+    endpoints: Vec<Endpoint<T>>,
+    routing: Routing,
+    unhealthy_after: u32,
+    unhealthy_for: Duration,
+    next: AtomicUsize,
+}
+
+/// Several [`RateLimitedClient`]s for the same service, e.g. one per RPC provider, each
+/// with its own limits.
+///
+/// A call is made on the first endpoint picked by the [`Routing`] and, if it fails, on the
+/// next ones until one succeeds. After `unhealthy_after` failures in a row an endpoint is
+/// skipped for `unhealthy_for`, unless every endpoint is unhealthy.
+///
+/// Example
+/// ```rust,no_run
+/// use solana_client::nonblocking::rpc_client::RpcClient;
+/// use solar::rate_limited::{ClientPool, Quota, RateLimitedClient, Routing};
+///
+/// # async fn run() -> eyre::Result<()> {
+/// let pool = ClientPool::builder()
+///     .routing(Routing::Priority)
+///     .endpoint(
+///         "helius",
+///         0,
+///         RateLimitedClient::builder(RpcClient::new("https://helius.example".to_string()))
+///             .quota(Quota::per_second(50))
+///             .build(),
+///     )
+///     .endpoint(
+///         "public",
+///         1,
+///         RateLimitedClient::per_second(RpcClient::new("https://public.example".to_string()), 10),
+///     )
+///     .build();
+///
+/// let slot = pool.call(|rpc| async move { rpc.get_slot().await }).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ClientPool<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Clone for ClientPool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+/// Builder for a [`ClientPool`], marking an endpoint unhealthy for 30s after 3 failures by
+/// default.
+pub struct ClientPoolBuilder<T> {
+    endpoints: Vec<Endpoint<T>>,
+    routing: Routing,
+    unhealthy_after: u32,
+    unhealthy_for: Duration,
+}
+
+impl<T> Default for ClientPoolBuilder<T> {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(),
+            routing: Routing::default(),
+            unhealthy_after: 3,
+            unhealthy_for: Duration::from_secs(30),
+        }
+    }
+}
+
+impl<T> ClientPoolBuilder<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an endpoint, `priority` only matters with [`Routing::Priority`].
+    pub fn endpoint(
+        mut self,
+        name: impl Into<String>,
+        priority: u32,
+        client: RateLimitedClient<T>,
+    ) -> Self {
+        self.endpoints.push(Endpoint {
+            name: name.into(),
+            priority,
+            client,
+            in_flight: AtomicUsize::new(0),
+            health: Mutex::new(Health::default()),
+        });
+        self
+    }
+
+    pub fn routing(mut self, routing: Routing) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    /// Failed calls in a row after which an endpoint is skipped.
+    pub fn unhealthy_after(mut self, unhealthy_after: u32) -> Self {
+        self.unhealthy_after = unhealthy_after.max(1);
+        self
+    }
+
+    /// How long an unhealthy endpoint is skipped before being tried again.
+    pub fn unhealthy_for(mut self, unhealthy_for: Duration) -> Self {
+        self.unhealthy_for = unhealthy_for;
+        self
+    }
+
+    /// Panics if no endpoint was added.
+    pub fn build(self) -> ClientPool<T> {
+        assert!(!self.endpoints.is_empty(), "client pool needs an endpoint");
+
+        ClientPool {
+            inner: Arc::new(Inner {
+                endpoints: self.endpoints,
+                routing: self.routing,
+                unhealthy_after: self.unhealthy_after,
+                unhealthy_for: self.unhealthy_for,
+                next: AtomicUsize::new(0),
+            }),
+        }
+    }
+}
+
+impl<T> ClientPool<T> {
+    pub fn builder() -> ClientPoolBuilder<T> {
+        ClientPoolBuilder::new()
+    }
+
+    pub fn status(&self) -> Vec<EndpointStatus> {
+        let now = Instant::now();
+
+        self.inner
+            .endpoints
+            .iter()
+            .map(|endpoint| EndpointStatus {
+                name: endpoint.name.clone(),
+                healthy: endpoint.is_healthy(now),
+                in_flight: endpoint.in_flight.load(Ordering::SeqCst),
+                consecutive_failures: endpoint.health.lock().unwrap().consecutive_failures,
+            })
+            .collect()
+    }
+
+    /// Makes the call on the endpoints in routing order until one succeeds, returning the
+    /// error of the last one otherwise.
+    pub async fn call<F, Fut, R, E>(&self, func: F) -> Result<R, E>
+    where
+        F: FnMut(Arc<T>) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+    {
+        self.call_weighted(1, func).await
+    }
+
+    /// Same as [`ClientPool::call`], using up `cost` units of the quota of every endpoint
+    /// tried.
+    pub async fn call_weighted<F, Fut, R, E>(&self, cost: u32, func: F) -> Result<R, E>
+    where
+        F: FnMut(Arc<T>) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+    {
+        self.call_classified(cost, func, |_| true).await
+    }
+
+    /// Same as [`ClientPool::call_weighted`], failing over only on the errors for which
+    /// `is_failure` returns true. Other errors, e.g. invalid params, are returned right away
+    /// without affecting the health of the endpoint.
+    pub async fn call_classified<F, Fut, R, E>(
+        &self,
+        cost: u32,
+        mut func: F,
+        is_failure: impl Fn(&E) -> bool,
+    ) -> Result<R, E>
+    where
+        F: FnMut(Arc<T>) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+    {
+        let mut last_err = None;
+
+        for index in self.route() {
+            let endpoint = &self.inner.endpoints[index];
+
+            let result = {
+                let _in_flight = InFlight::new(&endpoint.in_flight);
+                endpoint.client.call_weighted(cost, &mut func).await
+            };
+
+            match result {
+                Ok(result) => {
+                    self.record(endpoint, true);
+                    return Ok(result);
+                }
+                Err(err) if !is_failure(&err) => return Err(err),
+                Err(err) => {
+                    self.record(endpoint, false);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.expect("client pool has at least one endpoint"))
+    }
+
+    /// Indexes of the endpoints in the order to try them, the unhealthy ones last.
+    fn route(&self) -> Vec<usize> {
+        let endpoints = &self.inner.endpoints;
+        let mut route: Vec<usize> = match self.inner.routing {
+            Routing::RoundRobin => {
+                let start = self.inner.next.fetch_add(1, Ordering::Relaxed);
+                (0..endpoints.len())
+                    .map(|offset| (start + offset) % endpoints.len())
+                    .collect()
+            }
+            Routing::LeastLoaded => {
+                let mut route: Vec<usize> = (0..endpoints.len()).collect();
+                route.sort_by_key(|&index| endpoints[index].in_flight.load(Ordering::SeqCst));
+                route
+            }
+            Routing::Priority => {
+                let mut route: Vec<usize> = (0..endpoints.len()).collect();
+                route.sort_by_key(|&index| endpoints[index].priority);
+                route
+            }
+        };
+
+        let now = Instant::now();
+        route.sort_by_key(|&index| !endpoints[index].is_healthy(now));
+        route
+    }
+
+    fn record(&self, endpoint: &Endpoint<T>, success: bool) {
+        let mut health = endpoint.health.lock().unwrap();
+
+        if success {
+            *health = Health::default();
+            return;
+        }
+
+        health.end_quarantine(Instant::now());
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.inner.unhealthy_after {
+            #[cfg(feature = "log")]
+            log::warn!(client = "ClientPool"; "endpoint {} unhealthy", endpoint.name);
+            health.unhealthy_until = Some(Instant::now() + self.inner.unhealthy_for);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::sleep;
+
+    use super::*;
+
+    fn pool(routing: Routing, names: &[&'static str]) -> ClientPool<&'static str> {
+        let mut builder = ClientPool::builder()
+            .routing(routing)
+            .unhealthy_after(2)
+            .unhealthy_for(Duration::from_secs(10));
+        for (priority, name) in names.iter().enumerate() {
+            builder = builder.endpoint(
+                *name,
+                priority as u32,
+                RateLimitedClient::per_second(*name, 100),
+            );
+        }
+        builder.build()
+    }
+
+    async fn name(
+        pool: &ClientPool<&'static str>,
+        down: &'static str,
+    ) -> Result<&'static str, String> {
+        pool.call(|name| async move {
+            match *name == down {
+                true => Err(format!("{name} is down")),
+                false => Ok(*name),
+            }
+        })
+        .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_round_robin() {
+        let pool = pool(Routing::RoundRobin, &["a", "b", "c"]);
+
+        let mut names = Vec::new();
+        for _ in 0..6 {
+            names.push(name(&pool, "").await.unwrap());
+        }
+
+        assert_eq!(names, ["a", "b", "c", "a", "b", "c"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_priority_failover() {
+        let pool = pool(Routing::Priority, &["primary", "backup"]);
+
+        assert_eq!(name(&pool, "").await, Ok("primary"));
+        assert_eq!(name(&pool, "primary").await, Ok("backup"));
+        assert_eq!(name(&pool, "primary").await, Ok("backup"));
+        assert!(!pool.status()[0].healthy);
+
+        // skipped while unhealthy, even though it is back up
+        assert_eq!(name(&pool, "").await, Ok("backup"));
+        sleep(Duration::from_secs(10)).await;
+        assert_eq!(name(&pool, "").await, Ok("primary"));
+        assert_eq!(pool.status()[0].consecutive_failures, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_reset_after_quarantine() {
+        let pool = pool(Routing::Priority, &["primary", "backup"]);
+        for _ in 0..2 {
+            assert_eq!(name(&pool, "primary").await, Ok("backup"));
+        }
+        assert!(!pool.status()[0].healthy);
+
+        // a single failure after the quarantine is not enough to start another one
+        sleep(Duration::from_secs(10)).await;
+        assert_eq!(name(&pool, "primary").await, Ok("backup"));
+        assert!(pool.status()[0].healthy);
+        assert_eq!(pool.status()[0].consecutive_failures, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_least_loaded() {
+        let pool = pool(Routing::LeastLoaded, &["a", "b"]);
+
+        let busy = pool.clone();
+        let slow = tokio::spawn(async move {
+            busy.call(|name| async move {
+                sleep(Duration::from_secs(1)).await;
+                Ok::<_, String>(*name)
+            })
+            .await
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(pool.status()[0].in_flight, 1);
+
+        assert_eq!(name(&pool, "").await, Ok("b"));
+        assert_eq!(slow.await.unwrap(), Ok("a"));
+        assert_eq!(pool.status()[0].in_flight, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_all_failing() {
+        let pool = pool(Routing::RoundRobin, &["a"]);
+
+        assert_eq!(name(&pool, "a").await, Err("a is down".to_string()));
+        assert_eq!(name(&pool, "a").await, Err("a is down".to_string()));
+        // unhealthy endpoints are still tried when there is nothing else
+        assert_eq!(name(&pool, "").await, Ok("a"));
+    }
+
+    #[cfg(all(feature = "solana", feature = "axum"))]
+    #[tokio::test]
+    async fn test_json_rpc_failover() {
+        use axum::Json;
+        use axum::http::StatusCode;
+        use axum::routing::post;
+        use serde_json::{Value, json};
+        use solana_client::nonblocking::rpc_client::RpcClient;
+
+        async fn serve(slot: Option<u64>) -> String {
+            let app = axum::Router::new().route(
+                "/",
+                post(move |Json(request): Json<Value>| async move {
+                    match slot {
+                        Some(slot) => Ok(Json(json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "result": slot,
+                        }))),
+                        None => Err(StatusCode::SERVICE_UNAVAILABLE),
+                    }
+                }),
+            );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            url
+        }
+
+        let pool = ClientPool::builder()
+            .routing(Routing::Priority)
+            .unhealthy_after(1)
+            .endpoint(
+                "down",
+                0,
+                RateLimitedClient::per_second(RpcClient::new(serve(None).await), 10),
+            )
+            .endpoint(
+                "up",
+                1,
+                RateLimitedClient::per_second(RpcClient::new(serve(Some(42)).await), 10),
+            )
+            .build();
+
+        for _ in 0..3 {
+            let slot = pool.call(|rpc| async move { rpc.get_slot().await }).await;
+            assert_eq!(slot.unwrap(), 42);
+        }
+
+        let status = pool.status();
+        assert!(!status[0].healthy);
+        assert_eq!(status[0].consecutive_failures, 1);
+        assert!(status[1].healthy);
+    }
+}
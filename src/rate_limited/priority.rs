@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+/// Priority class of the calls made through a [`super::RateLimitedClient`] handle, see
+/// [`super::RateLimitedClient::with_priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Calls that must not wait behind the others, e.g. order execution.
+    Critical,
+    #[default]
+    Normal,
+    /// Calls that can be delayed, e.g. indexers and scans.
+    Background,
+}
+
+impl Priority {
+    pub(super) const ALL: [Priority; 3] = [Self::Critical, Self::Normal, Self::Background];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Slots of the quota burst and of the concurrency limit set aside for each priority, which
+/// calls of a lower priority cannot use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct Reserves([u32; 3]);
+
+impl Reserves {
+    pub(super) fn set(&mut self, priority: Priority, slots: u32) {
+        self.0[priority.index()] = slots;
+    }
+
+    /// Slots a call of `priority` has to leave free.
+    pub(super) fn above(&self, priority: Priority) -> u32 {
+        Priority::ALL
+            .iter()
+            .filter(|other| **other < priority)
+            .map(|other| self.0[other.index()])
+            .sum()
+    }
+}
+
+/// Concurrency limit letting a caller in only if it leaves `reserved` slots free.
+///
+/// Waiters queue per `reserved` level, i.e. per priority, and every freed slot is handed to
+/// the first waiter of the highest priority it fits: callers of the same priority get in in
+/// order, and a waiting low-priority caller does not hold up the callers behind it that are
+/// allowed to use the reserved slots.
+#[derive(Debug)]
+pub(super) struct Slots {
+    max: AtomicUsize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_use: usize,
+    // reserved slots -> waiters in arrival order
+    waiters: BTreeMap<usize, VecDeque<Waiter>>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    granted: oneshot::Sender<()>,
+}
+
+pub(super) struct Slot(Arc<Slots>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.in_use -= 1;
+        self.0.hand_out(&mut state);
+    }
+}
+
+/// Place in the queue of a caller waiting for a slot, leaving the queue when dropped.
+struct Waiting {
+    slots: Arc<Slots>,
+    reserved: usize,
+    id: u64,
+    granted: oneshot::Receiver<()>,
+    done: bool,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+
+        let mut state = self.slots.state.lock().unwrap();
+        // the slot is handed out under the lock, so it is either still queued or granted
+        if self.granted.try_recv().is_ok() {
+            state.in_use -= 1;
+            self.slots.hand_out(&mut state);
+        } else if let Some(queue) = state.waiters.get_mut(&self.reserved) {
+            queue.retain(|waiter| waiter.id != self.id);
+            if queue.is_empty() {
+                state.waiters.remove(&self.reserved);
+            }
+        }
+    }
+}
+
+impl Slots {
+    pub(super) fn new(max: usize) -> Self {
+        Self {
+            max: AtomicUsize::new(max),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub(super) fn max(&self) -> usize {
+        self.max.load(Ordering::SeqCst)
+    }
+
+    /// Changes the limit, the calls in progress above a lowered limit are left to finish.
+    pub(super) fn set_max(&self, max: usize) {
+        if self.max.swap(max, Ordering::SeqCst) < max {
+            self.hand_out(&mut self.state.lock().unwrap());
+        }
+    }
+
+    fn limit(&self, reserved: usize) -> usize {
+        // the lowest priority always keeps at least one slot
+        self.max().saturating_sub(reserved).max(1)
+    }
+
+    pub(super) async fn acquire(self: &Arc<Self>, reserved: usize) -> Slot {
+        let mut waiting = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(slot) = self.take(&mut state, reserved) {
+                return slot;
+            }
+
+            let (granted, receiver) = oneshot::channel();
+            let id = state.next_id;
+            state.next_id += 1;
+            state
+                .waiters
+                .entry(reserved)
+                .or_default()
+                .push_back(Waiter { id, granted });
+
+            Waiting {
+                slots: Arc::clone(self),
+                reserved,
+                id,
+                granted: receiver,
+                done: false,
+            }
+        };
+
+        // the sender is only dropped after handing out the slot
+        let _ = (&mut waiting.granted).await;
+        waiting.done = true;
+        Slot(Arc::clone(self))
+    }
+
+    /// Takes a slot only if one is free right now.
+    pub(super) fn try_acquire(self: &Arc<Self>, reserved: usize) -> Option<Slot> {
+        self.take(&mut self.state.lock().unwrap(), reserved)
+    }
+
+    fn take(self: &Arc<Self>, state: &mut State, reserved: usize) -> Option<Slot> {
+        // callers of the same or a higher priority that are already waiting go first
+        let queued_ahead = state.waiters.range(..=reserved).next().is_some();
+        if queued_ahead || state.in_use >= self.limit(reserved) {
+            return None;
+        }
+
+        state.in_use += 1;
+        Some(Slot(Arc::clone(self)))
+    }
+
+    /// Hands the free slots to the waiters, one each, highest priority first.
+    fn hand_out(&self, state: &mut State) {
+        while let Some(mut entry) = state.waiters.first_entry() {
+            if state.in_use >= self.limit(*entry.key()) {
+                return;
+            }
+
+            let queue = entry.get_mut();
+            let waiter = queue.pop_front();
+            if queue.is_empty() {
+                entry.remove();
+            }
+            if waiter.is_some_and(|waiter| waiter.granted.send(()).is_ok()) {
+                state.in_use += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserves() {
+        let mut reserves = Reserves::default();
+        reserves.set(Priority::Critical, 2);
+        reserves.set(Priority::Normal, 3);
+
+        assert_eq!(reserves.above(Priority::Critical), 0);
+        assert_eq!(reserves.above(Priority::Normal), 2);
+        assert_eq!(reserves.above(Priority::Background), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waiters_get_in_in_order() {
+        let slots = Arc::new(Slots::new(1));
+        let held = slots.acquire(0).await;
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for index in 0..5 {
+            let (slots, order) = (Arc::clone(&slots), Arc::clone(&order));
+            waiters.push(tokio::spawn(async move {
+                let _slot = slots.acquire(1).await;
+                order.lock().unwrap().push(index);
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }));
+            tokio::task::yield_now().await;
+        }
+
+        // a waiter giving up leaves the queue without taking a slot
+        let gave_up =
+            tokio::time::timeout(std::time::Duration::from_millis(1), slots.acquire(0)).await;
+        assert!(gave_up.is_err());
+
+        drop(held);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2, 3, 4]);
+        assert!(slots.try_acquire(0).is_some());
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::time::sleep;
+
+use super::RateLimitedClient;
+
+type Predicate<E> = Arc<dyn Fn(&E) -> bool + Send + Sync>;
+type RetryAfter<E> = Arc<dyn Fn(&E) -> Option<Duration> + Send + Sync>;
+
+/// How [`RateLimitedClient::call_with_retry`] retries a failed call.
+///
+/// The delay before retry `n` is `base_delay * 2^(n - 1)` capped at `max_delay`, of which up
+/// to `jitter` (a fraction between 0 and 1) is taken off at random so that clients failing
+/// together do not retry together. A `Retry-After` hint returned by `retry_after` replaces
+/// the computed delay, up to `max_retry_after`.
+///
+/// Example
+/// ```rust
+/// use std::time::Duration;
+///
+/// use solar::rate_limited::RetryPolicy;
+///
+/// #[derive(Debug)]
+/// enum RpcError {
+///     TooManyRequests { retry_after: Option<Duration> },
+///     Connection,
+///     InvalidParams,
+/// }
+///
+/// let policy = RetryPolicy::new(5)
+///     .with_delays(Duration::from_millis(100), Duration::from_secs(5))
+///     .with_retryable(|err: &RpcError| !matches!(err, RpcError::InvalidParams))
+///     .with_retry_after(|err: &RpcError| match err {
+///         RpcError::TooManyRequests { retry_after } => *retry_after,
+///         _ => None,
+///     });
+/// ```
+pub struct RetryPolicy<E> {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    max_retry_after: Duration,
+    jitter: f64,
+    retryable: Predicate<E>,
+    retry_after: RetryAfter<E>,
+}
+
+impl<E> Clone for RetryPolicy<E> {
+    fn clone(&self) -> Self {
+        Self {
+            max_attempts: self.max_attempts,
+            base_delay: self.base_delay,
+            max_delay: self.max_delay,
+            max_retry_after: self.max_retry_after,
+            jitter: self.jitter,
+            retryable: Arc::clone(&self.retryable),
+            retry_after: Arc::clone(&self.retry_after),
+        }
+    }
+}
+
+impl<E> fmt::Debug for RetryPolicy<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_delay", &self.base_delay)
+            .field("max_delay", &self.max_delay)
+            .field("max_retry_after", &self.max_retry_after)
+            .field("jitter", &self.jitter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E> RetryPolicy<E> {
+    /// Makes at most `max_attempts` attempts, the first one included, retrying every error
+    /// after 100ms to 10s, or after at most a minute when the server asks for a delay.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            max_retry_after: Duration::from_secs(60),
+            jitter: 0.5,
+            retryable: Arc::new(|_| true),
+            retry_after: Arc::new(|_| None),
+        }
+    }
+
+    /// Delay before the first retry, doubled after every attempt up to `max_delay`.
+    pub fn with_delays(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay.max(base_delay);
+        self
+    }
+
+    /// Fraction of the delay taken off at random, `0.0` to always wait the full delay.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Errors for which the call is made again, e.g. rate limits and connection errors
+    /// but not invalid params. Every error is retried by default.
+    pub fn with_retryable(
+        mut self,
+        retryable: impl Fn(&E) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.retryable = Arc::new(retryable);
+        self
+    }
+
+    /// Delay requested by the server, such as a `Retry-After` header, to use instead of
+    /// the backoff.
+    pub fn with_retry_after(
+        mut self,
+        retry_after: impl Fn(&E) -> Option<Duration> + Send + Sync + 'static,
+    ) -> Self {
+        self.retry_after = Arc::new(retry_after);
+        self
+    }
+
+    /// Longest delay requested by the server that is honored, longer ones are cut down to
+    /// it so that a bogus `Retry-After` does not stall the caller.
+    pub fn with_max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.max_retry_after = max_retry_after;
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Delay before making attempt `attempt + 1` after `err`, `None` if there is none left.
+    pub fn next_delay(&self, attempt: u32, err: &E) -> Option<Duration> {
+        if attempt >= self.max_attempts || !(self.retryable)(err) {
+            return None;
+        }
+        if let Some(retry_after) = (self.retry_after)(err) {
+            return Some(retry_after.min(self.max_retry_after));
+        }
+
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let jitter = backoff.mul_f64(self.jitter * rand::thread_rng().r#gen::<f64>());
+
+        Some(backoff - jitter)
+    }
+}
+
+impl<T> RateLimitedClient<T> {
+    /// Same as [`RateLimitedClient::call`], making the call again as long as `policy` allows.
+    ///
+    /// Every attempt goes through the limits like a separate call, so retries use up quota
+    /// and the concurrency slot is released while waiting for the next attempt.
+    pub async fn call_with_retry<F, Fut, R, E>(
+        &self,
+        policy: &RetryPolicy<E>,
+        func: F,
+    ) -> Result<R, E>
+    where
+        F: FnMut(Arc<T>) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+    {
+        self.call_weighted_with_retry(1, policy, func).await
+    }
+
+    /// [`RateLimitedClient::call_with_retry`] using up `cost` units of the quota per attempt.
+    pub async fn call_weighted_with_retry<F, Fut, R, E>(
+        &self,
+        cost: u32,
+        policy: &RetryPolicy<E>,
+        mut func: F,
+    ) -> Result<R, E>
+    where
+        F: FnMut(Arc<T>) -> Fut,
+        Fut: Future<Output = Result<R, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let err = match self.call_weighted(cost, &mut func).await {
+                Ok(result) => return Ok(result),
+                Err(err) => err,
+            };
+            let Some(delay) = policy.next_delay(attempt, &err) else {
+                return Err(err);
+            };
+
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::rate_limited::Quota;
+
+    #[derive(Debug, PartialEq)]
+    enum TestError {
+        Busy(Option<Duration>),
+        Fatal,
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy<TestError> {
+        RetryPolicy::new(max_attempts)
+            .with_delays(Duration::from_millis(100), Duration::from_millis(300))
+            .with_jitter(0.0)
+            .with_retryable(|err| matches!(err, TestError::Busy(_)))
+            .with_retry_after(|err| match err {
+                TestError::Busy(retry_after) => *retry_after,
+                TestError::Fatal => None,
+            })
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy(5);
+        let busy = TestError::Busy(None);
+
+        let delays: Vec<_> = (1..5)
+            .map(|attempt| policy.next_delay(attempt, &busy).unwrap().as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 300, 300]);
+        assert_eq!(policy.next_delay(5, &busy), None);
+        assert_eq!(policy.next_delay(1, &TestError::Fatal), None);
+
+        let jittered = policy.with_jitter(1.0).next_delay(3, &busy).unwrap();
+        assert!(jittered <= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_retry_after_is_capped() {
+        let policy = policy(5);
+        let day = TestError::Busy(Some(Duration::from_secs(86_400)));
+
+        assert_eq!(policy.next_delay(1, &day), Some(Duration::from_secs(60)));
+        let policy = policy.with_max_retry_after(Duration::from_secs(5));
+        assert_eq!(policy.next_delay(1, &day), Some(Duration::from_secs(5)));
+        assert_eq!(
+            policy.next_delay(1, &TestError::Busy(Some(Duration::from_secs(2)))),
+            Some(Duration::from_secs(2))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_until_success() {
+        let client = RateLimitedClient::builder(AtomicU32::new(0)).build();
+        let start = Instant::now();
+
+        let result = client
+            .call_with_retry(&policy(5), |calls| async move {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(TestError::Busy(None)),
+                    1 => Err(TestError::Busy(Some(Duration::from_secs(2)))),
+                    n => Ok(n),
+                }
+            })
+            .await;
+
+        assert_eq!(result, Ok(2));
+        assert_eq!(start.elapsed(), Duration::from_millis(100 + 2000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gives_up() {
+        let client = RateLimitedClient::builder(AtomicU32::new(0)).build();
+
+        let result = client
+            .call_with_retry(&policy(3), |calls| async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(TestError::Busy(None))
+            })
+            .await;
+        assert_eq!(result, Err(TestError::Busy(None)));
+        assert_eq!(client.inner().load(Ordering::SeqCst), 3);
+
+        let result = client
+            .call_with_retry(&policy(3), |calls| async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(TestError::Fatal)
+            })
+            .await;
+        assert_eq!(result, Err(TestError::Fatal));
+        assert_eq!(client.inner().load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_use_up_quota() {
+        let client = RateLimitedClient::builder(())
+            .quota(Quota::per_second(2).with_burst(1))
+            .build();
+        let policy = policy(4).with_delays(Duration::ZERO, Duration::ZERO);
+        let start = Instant::now();
+
+        let result = client
+            .call_with_retry(&policy, |_| async { Err::<(), _>(TestError::Busy(None)) })
+            .await;
+
+        assert!(result.is_err());
+        // four attempts at two per second, without any backoff
+        assert_eq!(start.elapsed(), Duration::from_millis(1500));
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use solana_client::client_error::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::RpcClientConfig;
+use solana_client::rpc_request::RpcRequest;
+use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+use solana_rpc_client::http_sender::HttpSender;
+use solana_sdk::commitment_config::CommitmentConfig;
+
+use super::{Quota, RateLimitedClient};
+
+/// Credits an RPC provider charges per method, `default` for the methods not listed.
+///
+/// Example
+/// ```rust
+/// use solar::rate_limited::MethodCosts;
+///
+/// let costs = MethodCosts::new(1)
+///     .with_cost("getProgramAccounts", 10)
+///     .with_cost("getSignaturesForAddress", 5);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodCosts {
+    default: u32,
+    costs: HashMap<String, u32>,
+}
+
+impl Default for MethodCosts {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl MethodCosts {
+    pub fn new(default: u32) -> Self {
+        Self {
+            default,
+            costs: HashMap::new(),
+        }
+    }
+
+    /// Cost of the JSON-RPC `method`, e.g. `getProgramAccounts`.
+    pub fn with_cost(mut self, method: impl Into<String>, cost: u32) -> Self {
+        self.costs.insert(method.into(), cost);
+        self
+    }
+
+    pub fn cost(&self, request: &RpcRequest) -> u32 {
+        self.costs
+            .get(&request.to_string())
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// [`RpcSender`] that spends the cost of every request from the quota of a
+/// [`RateLimitedClient`] before passing it on, so that a whole `RpcClient` stays within the
+/// credits of the provider plan.
+///
+/// Example
+/// ```rust,no_run
+/// use solana_sdk::commitment_config::CommitmentConfig;
+/// use solar::rate_limited::{MethodCosts, Quota, RateLimitedSender};
+///
+/// # async fn run() -> eyre::Result<()> {
+/// let costs = MethodCosts::new(1).with_cost("getProgramAccounts", 10);
+/// let rpc_client = RateLimitedSender::http(
+///     "https://api.mainnet-beta.solana.com",
+///     Quota::per_second(50),
+///     costs,
+/// )
+/// .into_rpc_client(CommitmentConfig::confirmed());
+///
+/// let slot = rpc_client.get_slot().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RateLimitedSender<S = HttpSender> {
+    client: RateLimitedClient<S>,
+    costs: MethodCosts,
+}
+
+impl RateLimitedSender<HttpSender> {
+    /// Sender over HTTP to `url` limited to `quota` credits.
+    pub fn http(url: impl ToString, quota: Quota, costs: MethodCosts) -> Self {
+        let client = RateLimitedClient::builder(HttpSender::new(url))
+            .quota(quota)
+            .build();
+
+        Self::new(client, costs)
+    }
+}
+
+impl<S: RpcSender + Send + Sync + 'static> RateLimitedSender<S> {
+    pub fn new(client: RateLimitedClient<S>, costs: MethodCosts) -> Self {
+        Self { client, costs }
+    }
+
+    pub fn into_rpc_client(self, commitment: CommitmentConfig) -> RpcClient {
+        RpcClient::new_sender(self, RpcClientConfig::with_commitment(commitment))
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: RpcSender + Send + Sync + 'static> RpcSender for RateLimitedSender<S> {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let cost = self.costs.cost(&request);
+
+        self.client
+            .call_weighted(
+                cost,
+                |sender| async move { sender.send(request, params).await },
+            )
+            .await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.client.inner().get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.client.inner().url()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::time::Instant;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingSender {
+        requests: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl RpcSender for CountingSender {
+        async fn send(
+            &self,
+            _request: RpcRequest,
+            _params: serde_json::Value,
+        ) -> Result<serde_json::Value> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            Ok(json!(42))
+        }
+
+        fn get_transport_stats(&self) -> RpcTransportStats {
+            RpcTransportStats::default()
+        }
+
+        fn url(&self) -> String {
+            "mock".to_string()
+        }
+    }
+
+    #[test]
+    fn test_method_costs() {
+        let costs = MethodCosts::new(1).with_cost("getProgramAccounts", 10);
+
+        assert_eq!(costs.cost(&RpcRequest::GetProgramAccounts), 10);
+        assert_eq!(costs.cost(&RpcRequest::GetBalance), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rpc_client_spends_method_costs() {
+        let sender = CountingSender::default();
+        let requests = sender.requests.clone();
+        let client = RateLimitedClient::builder(sender)
+            .quota(Quota::per_second(10))
+            .build();
+        let costs = MethodCosts::new(1).with_cost("getSlot", 5);
+        let rpc_client =
+            RateLimitedSender::new(client, costs).into_rpc_client(CommitmentConfig::confirmed());
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(rpc_client.get_slot().await.unwrap(), 42);
+        }
+
+        // 15 credits with a burst of 10 at 10 credits per second
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(rpc_client.url(), "mock");
+    }
+}